@@ -1,6 +1,6 @@
 use anyhow::Result;
-use axum::{routing::post, Json, Router};
-use prql_compiler::{compile, ErrorMessages, Options, Target};
+use axum::{response::IntoResponse, routing::post, Json, Router};
+use prql_compiler::ErrorMessages;
 use serde::{Deserialize, Serialize};
 
 mod pipeline;
@@ -20,14 +20,23 @@ async fn main() {
         .unwrap();
 }
 
-async fn to_prql(Json(request): Json<Request>) -> String {
-    request
-        .to_prql()
-        .expect("Could not convert request to PRQL")
+// Tells whether the results of the query are exact or approximate
+const EXACT_RESULTS_HEADER: &str = "x-exact-results";
+
+async fn to_prql(Json(request): Json<Request>) -> impl IntoResponse {
+    (
+        [(EXACT_RESULTS_HEADER, request.is_exact().to_string())],
+        request
+            .to_prql()
+            .expect("Could not convert request to PRQL"),
+    )
 }
 
-async fn to_sql(Json(request): Json<Request>) -> String {
-    request.to_sql().expect("Could not convert request to SQL")
+async fn to_sql(Json(request): Json<Request>) -> impl IntoResponse {
+    (
+        [(EXACT_RESULTS_HEADER, request.is_exact().to_string())],
+        request.to_sql().expect("Could not convert request to SQL"),
+    )
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -42,17 +51,11 @@ impl Request {
     }
 
     fn to_sql(&self) -> Result<String, ErrorMessages> {
-        let target = match self.dialect {
-            Dialect::Postgres => Target::Sql(Some(prql_compiler::sql::Dialect::Postgres)),
-            Dialect::BigQuery => Target::Sql(Some(prql_compiler::sql::Dialect::BigQuery)),
-        };
-        let opts = Options {
-            format: false,
-            target,
-            signature_comment: false,
-            color: false,
-        };
-        compile(&self.to_prql()?, &opts)
+        translate::to_sql(&self.to_prql()?, &self.dialect)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.pipeline.is_exact(&self.dialect)
     }
 }
 
@@ -122,7 +125,7 @@ mod tests {
                     {
                         "columns": ["City"],
                         "newcolumns": ["City"],
-                        "aggfunction": "first",
+                        "aggfunction": "min",
                     },
                     {
                         "columns": ["Price", "Quantity"],
@@ -143,8 +146,8 @@ mod tests {
     }
 
     #[rstest]
-    #[case::postgres("postgres", r#"SELECT *, MIN("City") OVER () AS "City", SUM("Price") OVER () AS "Price_sum", SUM("Quantity") OVER () AS "Somme des quantités" FROM "al bums""#)]
-    #[case::bigquery("bigquery", "SELECT *, MIN(`City`) OVER () AS `City`, SUM(`Price`) OVER () AS `Price_sum`, SUM(`Quantity`) OVER () AS `Somme des quantités` FROM `al bums`")]
+    #[case::postgres("postgres", r#"SELECT *, MIN("City") OVER () AS "City", COALESCE(SUM("Price") OVER (), 0) AS "Price_sum", COALESCE(SUM("Quantity") OVER (), 0) AS "Somme des quantités" FROM "al bums""#)]
+    #[case::bigquery("bigquery", "SELECT *, MIN(`City`) OVER () AS `City`, COALESCE(SUM(`Price`) OVER (), 0) AS `Price_sum`, COALESCE(SUM(`Quantity`) OVER (), 0) AS `Somme des quantités` FROM `al bums`")]
     fn aggregation_group_no_keep_yes(#[case] dialect: &str, #[case] sql: &str) {
         let request = json!(
         {
@@ -160,7 +163,7 @@ mod tests {
                         {
                             "columns": ["City"],
                             "newcolumns": ["City"],
-                            "aggfunction": "first",
+                            "aggfunction": "min",
                         },
                         {
                             "columns": ["Price", "Quantity"],
@@ -176,7 +179,7 @@ mod tests {
         let request: Request = serde_json::from_value(request).unwrap();
         assert_eq!(
             request.to_prql().unwrap(),
-            "from `al bums` | derive { `City` = min `City`, `Price_sum` = sum `Price`, `Somme des quantités` = sum `Quantity` }"
+            "from `al bums` | derive { `City` = min `City`, `Price_sum` = (sum `Price`) ?? 0, `Somme des quantités` = (sum `Quantity`) ?? 0 }"
         );
         assert_eq!(request.to_sql().unwrap(), sql);
     }
@@ -200,7 +203,7 @@ mod tests {
                         {
                             "columns": ["City"],
                             "newcolumns": ["City"],
-                            "aggfunction": "first",
+                            "aggfunction": "min",
                         },
                         {
                             "columns": ["Price", "Quantity"],
@@ -221,8 +224,8 @@ mod tests {
     }
 
     #[rstest]
-    #[case::postgres("postgres", r#"SELECT *, MIN("City") OVER (PARTITION BY col, "other col") AS "City", SUM("Price") OVER (PARTITION BY col, "other col") AS "Price_sum", SUM("Quantity") OVER (PARTITION BY col, "other col") AS "Somme des quantités" FROM "al bums""#)]
-    #[case::bigquery("bigquery", "SELECT *, MIN(`City`) OVER (PARTITION BY col, `other col`) AS `City`, SUM(`Price`) OVER (PARTITION BY col, `other col`) AS `Price_sum`, SUM(`Quantity`) OVER (PARTITION BY col, `other col`) AS `Somme des quantités` FROM `al bums`")]
+    #[case::postgres("postgres", r#"SELECT *, MIN("City") OVER (PARTITION BY col, "other col") AS "City", COALESCE(SUM("Price") OVER (PARTITION BY col, "other col"), 0) AS "Price_sum", COALESCE(SUM("Quantity") OVER (PARTITION BY col, "other col"), 0) AS "Somme des quantités" FROM "al bums""#)]
+    #[case::bigquery("bigquery", "SELECT *, MIN(`City`) OVER (PARTITION BY col, `other col`) AS `City`, COALESCE(SUM(`Price`) OVER (PARTITION BY col, `other col`), 0) AS `Price_sum`, COALESCE(SUM(`Quantity`) OVER (PARTITION BY col, `other col`), 0) AS `Somme des quantités` FROM `al bums`")]
     fn aggregation_group_yes_keep_yes(#[case] dialect: &str, #[case] sql: &str) {
         let request = json!(
         {
@@ -239,7 +242,7 @@ mod tests {
                         {
                             "columns": ["City"],
                             "newcolumns": ["City"],
-                            "aggfunction": "first",
+                            "aggfunction": "min",
                         },
                         {
                             "columns": ["Price", "Quantity"],
@@ -255,11 +258,41 @@ mod tests {
         let request: Request = serde_json::from_value(request).unwrap();
         assert_eq!(
             request.to_prql().unwrap(),
-            "from `al bums` | group { `col`, `other col` } ( window rows:.. ( derive { `City` = min `City`, `Price_sum` = sum `Price`, `Somme des quantités` = sum `Quantity` } ) )"
+            "from `al bums` | group { `col`, `other col` } ( window rows:.. ( derive { `City` = min `City`, `Price_sum` = (sum `Price`) ?? 0, `Somme des quantités` = (sum `Quantity`) ?? 0 } ) )"
         );
         assert_eq!(request.to_sql().unwrap(), sql);
     }
 
+    #[rstest]
+    #[case::first("first")]
+    #[case::last("last")]
+    fn aggregation_first_without_order_by(#[case] function: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["col"],
+                    "aggregations": [
+                        { "columns": ["City"], "newcolumns": ["City"], "aggfunction": function }
+                    ],
+                }
+            ],
+            "dialect": "postgres",
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        // The first value of a group is only defined in the order of a column
+        assert_eq!(
+            request.to_prql().unwrap_err().to_string(),
+            "The first and last aggregations need an orderBy column"
+        );
+    }
+
     #[rstest]
     #[case::postgres("postgres", r#"SELECT * FROM "al bums" WHERE "City" = 'Paris'"#)]
     #[case::bigquery("bigquery", "SELECT * FROM `al bums` WHERE `City` = 'Paris'")]
@@ -444,4 +477,1353 @@ mod tests {
         assert_eq!(request.to_prql().unwrap(), prql);
         assert_eq!(request.to_sql().unwrap(), sql);
     }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"SELECT *, CASE WHEN "Quantity" >= 100 THEN "Unit Price" * 0.8 WHEN "Client" = 'VIP' OR "Quantity" >= 10 THEN "Unit Price" * 0.9 ELSE "Unit Price" END AS "discounted price" FROM "al bums""#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "SELECT *, CASE WHEN `Quantity` >= 100 THEN `Unit Price` * 0.8 WHEN `Client` = 'VIP' OR `Quantity` >= 10 THEN `Unit Price` * 0.9 ELSE `Unit Price` END AS `discounted price` FROM `al bums`"
+    )]
+    fn ifthenelse_nested(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "ifthenelse",
+                    "newColumn": "discounted price",
+                    "if": {
+                        "column": "Quantity",
+                        "value": 100,
+                        "operator": "gte"
+                    },
+                    "then": { "formula": "[Unit Price] * 0.8" },
+                    "else": {
+                        "if": {
+                            "or": [
+                                { "column": "Client", "operator": "eq", "value": "VIP" },
+                                { "column": "Quantity", "operator": "gte", "value": 10 }
+                            ]
+                        },
+                        "then": { "formula": "[Unit Price] * 0.9" },
+                        "else": { "formula": "[Unit Price]" }
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_prql().unwrap(),
+            r#"from `al bums` | derive { `discounted price` = case [ `Quantity` >= 100 => `Unit Price` * 0.8, (`Client` == "VIP" || `Quantity` >= 10) => `Unit Price` * 0.9, true => `Unit Price` ] }"#
+        );
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"WITH step_1 AS (SELECT * FROM sales), table_1 AS (SELECT "YEAR", COALESCE(SUM("VALUE"), 0) AS "VALUE" FROM step_1 GROUP BY "YEAR"), table_0 AS (SELECT "YEAR", 'All countries' AS "COUNTRY", "VALUE" FROM table_1) SELECT "YEAR", "COUNTRY", COALESCE(SUM("VALUE"), 0) AS "VALUE" FROM step_1 GROUP BY "YEAR", "COUNTRY" UNION ALL SELECT * FROM table_0"#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "WITH step_1 AS (SELECT * FROM sales), table_1 AS (SELECT `YEAR`, COALESCE(SUM(`VALUE`), 0) AS `VALUE` FROM step_1 GROUP BY `YEAR`), table_0 AS (SELECT `YEAR`, 'All countries' AS `COUNTRY`, `VALUE` FROM table_1) SELECT `YEAR`, `COUNTRY`, COALESCE(SUM(`VALUE`), 0) AS `VALUE` FROM step_1 GROUP BY `YEAR`, `COUNTRY` UNION ALL SELECT * FROM table_0"
+    )]
+    fn totals(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "totals",
+                    "totalDimensions": [
+                        { "totalColumn": "COUNTRY", "totalRowsLabel": "All countries" }
+                    ],
+                    "aggregations": [
+                        { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+                    ],
+                    "groups": ["YEAR"]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_prql().unwrap(),
+            "let step_1 = (from `sales`)\n\nfrom step_1 | group { `YEAR`, `COUNTRY` } ( aggregate { `VALUE` = sum `VALUE` } ) | select { `YEAR`, `COUNTRY`, `VALUE` } | append (from step_1 | group { `YEAR` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `COUNTRY` = \"All countries\" } | select { `YEAR`, `COUNTRY`, `VALUE` })"
+        );
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[test]
+    fn totals_as_first_step() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "totals",
+                    "totalDimensions": [
+                        { "totalColumn": "COUNTRY", "totalRowsLabel": "All countries" }
+                    ],
+                    "aggregations": [
+                        { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+                    ]
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert!(request.to_prql().is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"WITH step_1 AS (SELECT * FROM sales), table_1 AS (SELECT "CONTINENT", COALESCE(SUM("VALUE"), 0) AS "VALUE" FROM step_1 GROUP BY "CONTINENT"), table_2 AS (SELECT "CONTINENT", "COUNTRY", COALESCE(SUM("VALUE"), 0) AS "VALUE" FROM step_1 GROUP BY "CONTINENT", "COUNTRY"), table_0 AS (SELECT "CONTINENT", "COUNTRY", "COUNTRY" AS label, 'COUNTRY' AS level, "CONTINENT" AS parent, "VALUE" FROM table_2) SELECT "CONTINENT", NULL AS "COUNTRY", "CONTINENT" AS label, 'CONTINENT' AS level, NULL AS parent, "VALUE" FROM table_1 UNION ALL SELECT * FROM table_0"#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "WITH step_1 AS (SELECT * FROM sales), table_1 AS (SELECT `CONTINENT`, COALESCE(SUM(`VALUE`), 0) AS `VALUE` FROM step_1 GROUP BY `CONTINENT`), table_2 AS (SELECT `CONTINENT`, `COUNTRY`, COALESCE(SUM(`VALUE`), 0) AS `VALUE` FROM step_1 GROUP BY `CONTINENT`, `COUNTRY`), table_0 AS (SELECT `CONTINENT`, `COUNTRY`, `COUNTRY` AS label, 'COUNTRY' AS level, `CONTINENT` AS parent, `VALUE` FROM table_2) SELECT `CONTINENT`, NULL AS `COUNTRY`, `CONTINENT` AS label, 'CONTINENT' AS level, NULL AS parent, `VALUE` FROM table_1 UNION ALL SELECT * FROM table_0"
+    )]
+    fn rollup(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "rollup",
+                    "hierarchy": ["CONTINENT", "COUNTRY"],
+                    "aggregations": [
+                        { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+                    ]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_prql().unwrap(),
+            "let step_1 = (from `sales`)\n\nfrom step_1 | group { `CONTINENT` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `COUNTRY` = null, `label` = `CONTINENT`, `level` = \"CONTINENT\", `parent` = null } | select { `CONTINENT`, `COUNTRY`, `label`, `level`, `parent`, `VALUE` } | append (from step_1 | group { `CONTINENT`, `COUNTRY` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `label` = `COUNTRY`, `level` = \"COUNTRY\", `parent` = `CONTINENT` } | select { `CONTINENT`, `COUNTRY`, `label`, `level`, `parent`, `VALUE` })"
+        );
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"WITH step_1 AS (SELECT * FROM sales), table_4 AS (SELECT COALESCE(SUM(revenue), 0) AS revenue FROM step_1 WHERE year = 2019), table_5 AS (SELECT COALESCE(SUM(CASE WHEN year = 2020 THEN revenue ELSE 0 END), 0) AS _expr_1, COALESCE(SUM(CASE WHEN year = 2019 THEN revenue ELSE 0 END), 0) AS _expr_2, city FROM step_1 WHERE year = 2019 OR year = 2020 GROUP BY city), table_0 AS (SELECT city AS "LABEL_WATERFALL", city AS "GROUP_WATERFALL", 'parent' AS "TYPE_WATERFALL", _expr_1 - _expr_2 AS revenue, 1 AS __waterfall_rank FROM table_5), table_6 AS (SELECT COALESCE(SUM(revenue), 0) AS revenue FROM step_1 WHERE year = 2020), table_1 AS (SELECT '2020' AS "LABEL_WATERFALL", '2020' AS "GROUP_WATERFALL", NULL AS "TYPE_WATERFALL", revenue, 2 AS __waterfall_rank FROM table_6), table_2 AS (SELECT '2019' AS "LABEL_WATERFALL", '2019' AS "GROUP_WATERFALL", NULL AS "TYPE_WATERFALL", revenue, 0 AS _expr_0 FROM table_4 UNION ALL SELECT * FROM table_0 UNION ALL SELECT * FROM table_1), table_3 AS (SELECT "LABEL_WATERFALL", "GROUP_WATERFALL", "TYPE_WATERFALL", revenue, _expr_0 FROM table_2) SELECT "LABEL_WATERFALL", "GROUP_WATERFALL", "TYPE_WATERFALL", revenue FROM table_3 ORDER BY _expr_0, revenue DESC"#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "WITH step_1 AS (SELECT * FROM sales), table_4 AS (SELECT COALESCE(SUM(revenue), 0) AS revenue FROM step_1 WHERE year = 2019), table_5 AS (SELECT COALESCE(SUM(CASE WHEN year = 2020 THEN revenue ELSE 0 END), 0) AS _expr_1, COALESCE(SUM(CASE WHEN year = 2019 THEN revenue ELSE 0 END), 0) AS _expr_2, city FROM step_1 WHERE year = 2019 OR year = 2020 GROUP BY city), table_0 AS (SELECT city AS `LABEL_WATERFALL`, city AS `GROUP_WATERFALL`, 'parent' AS `TYPE_WATERFALL`, _expr_1 - _expr_2 AS revenue, 1 AS __waterfall_rank FROM table_5), table_6 AS (SELECT COALESCE(SUM(revenue), 0) AS revenue FROM step_1 WHERE year = 2020), table_1 AS (SELECT '2020' AS `LABEL_WATERFALL`, '2020' AS `GROUP_WATERFALL`, NULL AS `TYPE_WATERFALL`, revenue, 2 AS __waterfall_rank FROM table_6), table_2 AS (SELECT '2019' AS `LABEL_WATERFALL`, '2019' AS `GROUP_WATERFALL`, NULL AS `TYPE_WATERFALL`, revenue, 0 AS _expr_0 FROM table_4 UNION ALL SELECT * FROM table_0 UNION ALL SELECT * FROM table_1), table_3 AS (SELECT `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, revenue, _expr_0 FROM table_2) SELECT `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, revenue FROM table_3 ORDER BY _expr_0, revenue DESC"
+    )]
+    fn waterfall(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "waterfall",
+                    "valueColumn": "revenue",
+                    "milestonesColumn": "year",
+                    "start": 2019,
+                    "end": 2020,
+                    "labelsColumn": "city",
+                    "sortBy": "value",
+                    "order": "desc"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().unwrap(), "let step_1 = (from `sales`)\n\nfrom step_1 | filter `year` == 2019 | aggregate { `revenue` = sum `revenue` } | derive { `LABEL_WATERFALL` = \"2019\", `GROUP_WATERFALL` = \"2019\", `TYPE_WATERFALL` = null, `__waterfall_rank` = 0 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` } | append (from step_1 | filter (`year` == 2019 || `year` == 2020) | group { `city` } ( aggregate { `__start` = sum (case [ `year` == 2019 => `revenue`, true => 0 ]), `__end` = sum (case [ `year` == 2020 => `revenue`, true => 0 ]) } ) | derive { `LABEL_WATERFALL` = `city`, `GROUP_WATERFALL` = `city`, `TYPE_WATERFALL` = \"parent\", `revenue` = `__end` - `__start`, `__waterfall_rank` = 1 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` }) | append (from step_1 | filter `year` == 2020 | aggregate { `revenue` = sum `revenue` } | derive { `LABEL_WATERFALL` = \"2020\", `GROUP_WATERFALL` = \"2020\", `TYPE_WATERFALL` = null, `__waterfall_rank` = 2 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` }) | sort { `__waterfall_rank`, -`revenue` } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue` }");
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"from `sales` | group { `city` } ( aggregate { `average` = average `price`, `median` = s"PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY {`price`})" } )"#,
+        r#"SELECT city, AVG(price) AS average, PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY price) AS median FROM sales GROUP BY city"#,
+        true
+    )]
+    #[case::bigquery(
+        "bigquery",
+        r#"from `sales` | group { `city` } ( aggregate { `average` = average `price`, `median` = s"APPROX_QUANTILES({`price`}, 2)[OFFSET(1)]" } )"#,
+        "SELECT city, AVG(price) AS average, APPROX_QUANTILES(price, 2)[OFFSET(1)] AS median FROM sales GROUP BY city",
+        false
+    )]
+    fn statistics(
+        #[case] dialect: &str,
+        #[case] prql: &str,
+        #[case] sql: &str,
+        #[case] exact: bool,
+    ) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "statistics",
+                    "column": "price",
+                    "groupbyColumns": ["city"],
+                    "statistics": ["average"],
+                    "quantiles": [{ "label": "median", "nth": 1, "order": 2 }]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().unwrap(), prql);
+        assert_eq!(request.to_sql().unwrap(), sql);
+        assert_eq!(request.is_exact(), exact);
+    }
+
+    #[test]
+    fn statistics_exact_quantiles_bigquery() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "statistics",
+                    "column": "price",
+                    "groupbyColumns": ["city"],
+                    "statistics": ["average"],
+                    "quantiles": [{ "label": "median", "nth": 1, "order": 2 }],
+                    "exactQuantiles": true
+                }
+            ],
+            "dialect": "bigquery"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), "SELECT city, AVG(price) AS average, (ARRAY_AGG(price IGNORE NULLS ORDER BY price)[SAFE_OFFSET(CAST(FLOOR(0.5 * (COUNT(price) - 1)) AS INT64))] * (1 - (0.5 * (COUNT(price) - 1) - FLOOR(0.5 * (COUNT(price) - 1)))) + ARRAY_AGG(price IGNORE NULLS ORDER BY price)[SAFE_OFFSET(CAST(CEIL(0.5 * (COUNT(price) - 1)) AS INT64))] * (0.5 * (COUNT(price) - 1) - FLOOR(0.5 * (COUNT(price) - 1)))) AS median FROM sales GROUP BY city");
+        assert!(request.is_exact());
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", r#"SELECT DISTINCT "Country", "City" FROM "al bums""#)]
+    #[case::bigquery("bigquery", "SELECT DISTINCT `Country`, `City` FROM `al bums`")]
+    fn uniquegroups(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "uniquegroups",
+                    "on": ["Country", "City"]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().unwrap(), "from `al bums` | group { `Country`, `City` } ( take 1 ) | select { `Country`, `City` }");
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"SELECT "Country", "City", COUNT(*) AS "rows count" FROM "al bums" GROUP BY "Country", "City""#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "SELECT `Country`, `City`, COUNT(*) AS `rows count` FROM `al bums` GROUP BY `Country`, `City`"
+    )]
+    fn uniquegroups_with_count(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "uniquegroups",
+                    "on": ["Country", "City"],
+                    "countColumn": "rows count"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().unwrap(), "from `al bums` | group { `Country`, `City` } ( aggregate { `rows count` = count this } )");
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", r#"from `al bums` | select { `Title`, `Quantity` = s"CASE WHEN pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'INT8') THEN CAST(CAST({`Quantity`} AS TEXT) AS INT8) WHEN pg_typeof({`Quantity`}) IN ('text'::regtype, 'character varying'::regtype, 'character'::regtype) OR NOT pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'NUMERIC') THEN NULL WHEN pg_input_is_valid(CAST(ROUND(CAST(CAST({`Quantity`} AS TEXT) AS NUMERIC)) AS TEXT), 'INT8') THEN CAST(ROUND(CAST(CAST({`Quantity`} AS TEXT) AS NUMERIC)) AS INT8) END", `Price` }"#, r#"SELECT "Title", CASE WHEN pg_input_is_valid(CAST("Quantity" AS TEXT), 'INT8') THEN CAST(CAST("Quantity" AS TEXT) AS INT8) WHEN pg_typeof("Quantity") IN ('text'::regtype, 'character varying'::regtype, 'character'::regtype) OR NOT pg_input_is_valid(CAST("Quantity" AS TEXT), 'NUMERIC') THEN NULL WHEN pg_input_is_valid(CAST(ROUND(CAST(CAST("Quantity" AS TEXT) AS NUMERIC)) AS TEXT), 'INT8') THEN CAST(ROUND(CAST(CAST("Quantity" AS TEXT) AS NUMERIC)) AS INT8) END AS "Quantity", "Price" FROM "al bums""#)]
+    #[case::bigquery("bigquery", r#"from `al bums` | select { `Title`, `Quantity` = s"SAFE_CAST({`Quantity`} AS INT64)", `Price` }"#, "SELECT `Title`, SAFE_CAST(`Quantity` AS INT64) AS `Quantity`, `Price` FROM `al bums`")]
+    fn convert_safe(#[case] dialect: &str, #[case] prql: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                    "columns": ["Title", "Quantity", "Price"],
+                },
+                {
+                    "name": "convert",
+                    "columns": ["Quantity"],
+                    "data_type": "integer",
+                    "safe": true
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().unwrap(), prql);
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", r#"SELECT a, b, CAST(a AS TEXT) AS c FROM "al bums""#)]
+    #[case::bigquery("bigquery", "SELECT a, b, CAST(a AS STRING) AS c FROM `al bums`")]
+    fn convert_after_duplicate(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                    "columns": ["a", "b"],
+                },
+                {
+                    "name": "duplicate",
+                    "column": "a",
+                    "newColumnName": "c"
+                },
+                {
+                    "name": "convert",
+                    "columns": ["c"],
+                    "data_type": "text"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        r#"WITH table_0 AS (SELECT a, COALESCE(SUM(b), 0) AS _expr_0 FROM "al bums" GROUP BY a) SELECT a, CAST(_expr_0 AS TEXT) AS n FROM table_0"#
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "WITH table_0 AS (SELECT a, COALESCE(SUM(b), 0) AS _expr_0 FROM `al bums` GROUP BY a) SELECT a, CAST(_expr_0 AS STRING) AS n FROM table_0"
+    )]
+    fn convert_after_aggregate(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["a"],
+                    "aggregations": [
+                        {
+                            "columns": ["b"],
+                            "newcolumns": ["n"],
+                            "aggfunction": "sum",
+                        },
+                    ],
+                },
+                {
+                    "name": "convert",
+                    "columns": ["n"],
+                    "data_type": "text"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[test]
+    fn convert_unknown_columns_bigquery() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "convert",
+                    "columns": ["Quantity"],
+                    "data_type": "integer"
+                }
+            ],
+            "dialect": "bigquery"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_sql().unwrap(),
+            "WITH table_0 AS (SELECT * REPLACE (CAST(Quantity AS INT64) AS Quantity) FROM (SELECT * FROM `al bums`) AS previous_step) SELECT * FROM table_0"
+        );
+    }
+
+    #[test]
+    fn convert_unknown_columns_postgres() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "convert",
+                    "columns": ["Quantity"],
+                    "data_type": "integer"
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_prql().unwrap_err().to_string(),
+            "The columns of the input must be known to replace some of them with this dialect: list them in the columns of the domain step, and avoid steps whose output columns are unknown such as customsql"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", r#"SELECT *, "Price" AS "Original price" FROM "al bums""#)]
+    #[case::bigquery("bigquery", "SELECT *, `Price` AS `Original price` FROM `al bums`")]
+    fn duplicate(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "duplicate",
+                    "column": "Price",
+                    "newColumnName": "Original price"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_prql().unwrap(),
+            "from `al bums` | derive { `Original price` = `Price` }"
+        );
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::domain_columns(json!([
+        {
+            "name": "domain",
+            "domain": "al bums",
+            "columns": ["Price", "Original price"],
+        },
+        {
+            "name": "duplicate",
+            "column": "Price",
+            "newColumnName": "Original price"
+        }
+    ]))]
+    #[case::aggregated_columns(json!([
+        {
+            "name": "domain",
+            "domain": "al bums",
+        },
+        {
+            "name": "aggregate",
+            "on": ["City"],
+            "aggregations": [
+                { "columns": ["Price"], "newcolumns": ["Price"], "aggfunction": "sum" }
+            ]
+        },
+        {
+            "name": "duplicate",
+            "column": "Price",
+            "newColumnName": "City"
+        }
+    ]))]
+    fn duplicate_existing_column(#[case] pipeline: serde_json::Value) {
+        let request = json!(
+        {
+            "pipeline": pipeline,
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert!(request.to_prql().is_err());
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "WITH table_0 AS (SELECT Country, COUNT(*) AS total FROM (SELECT * FROM \"al bums\" WHERE \"City\" = 'Paris') AS previous_step GROUP BY 1) SELECT * FROM table_0 WHERE total > 10")]
+    #[case::bigquery("bigquery", "WITH table_0 AS (SELECT Country, COUNT(*) AS total FROM (SELECT * FROM `al bums` WHERE `City` = 'Paris') AS previous_step GROUP BY 1) SELECT * FROM table_0 WHERE total > 10")]
+    fn customsql_wrapping_previous_steps(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                      "column": "City",
+                      "value": "Paris",
+                      "operator": "eq"
+                    }
+                },
+                {
+                    "name": "customsql",
+                    "query": "SELECT Country, COUNT(*) AS total FROM ##PREVIOUS_STEP## GROUP BY 1"
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                      "column": "total",
+                      "value": 10,
+                      "operator": "gt"
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[test]
+    fn customsql_first_step_only() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "albums",
+                    "table": true,
+                },
+                {
+                    "name": "customsql",
+                    "query": "SELECT * FROM albums"
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert!(request.to_prql().is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        "SELECT *, \"First name\" COLLATE \"C\" = \"Nickname\" COLLATE \"C\" AS \"same name\" FROM people"
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "SELECT *, `First name` = `Nickname` AS `same name` FROM people"
+    )]
+    fn comparetext(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "people",
+                    "table": true,
+                },
+                {
+                    "name": "comparetext",
+                    "newColumnName": "same name",
+                    "strCol1": "First name",
+                    "strCol2": "Nickname"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT *, ABS(\"margin\") AS \"abs margin\" FROM sales")]
+    #[case::bigquery("bigquery", "SELECT *, ABS(`margin`) AS `abs margin` FROM sales")]
+    fn absolutevalue(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "absolutevalue",
+                    "column": "margin",
+                    "newColumn": "abs margin"
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "WITH table_0 AS (SELECT territory, COALESCE(SUM(population), 0) AS population, ST_Union(geometry) AS _expr_0 FROM regions WHERE territory IS NOT NULL GROUP BY territory) SELECT territory, ST_Simplify(_expr_0, 100) AS geometry, population FROM table_0")]
+    #[case::bigquery("bigquery", "WITH table_0 AS (SELECT territory, COALESCE(SUM(population), 0) AS population, ST_UNION_AGG(geometry) AS _expr_0 FROM regions WHERE territory IS NOT NULL GROUP BY territory) SELECT territory, ST_Simplify(_expr_0, 100) AS geometry, population FROM table_0")]
+    fn dissolve_and_simplify(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "regions",
+                    "table": true,
+                },
+                {
+                    "name": "dissolve",
+                    "groups": ["territory"],
+                    "aggregations": [
+                        { "columns": ["population"], "newcolumns": ["population"], "aggfunction": "sum" }
+                    ]
+                },
+                {
+                    "name": "simplify",
+                    "tolerance": 100
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[test]
+    fn simplify_unknown_columns() {
+        let request = |dialect: &str| -> Request {
+            serde_json::from_value(json!(
+            {
+                "pipeline": [
+                    {
+                        "name": "domain",
+                        "domain": "regions",
+                        "table": true,
+                    },
+                    {
+                        "name": "simplify",
+                        "tolerance": 100
+                    }
+                ],
+                "dialect": dialect
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            request("bigquery").to_sql().unwrap(),
+            "WITH table_0 AS (SELECT * REPLACE (ST_Simplify(geometry, 100) AS geometry) FROM (SELECT * FROM regions) AS previous_step) SELECT * FROM table_0"
+        );
+        assert!(request("postgres").to_prql().is_err());
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT * FROM sales WHERE \"sale date\" >= CAST(CURRENT_DATE + INTERVAL '-1 months' AS DATE) AND \"sale date\" <= DATE '2023-12-31'")]
+    #[case::bigquery("bigquery", "SELECT * FROM sales WHERE `sale date` >= DATE_ADD(CURRENT_DATE, INTERVAL -1 MONTH) AND `sale date` <= DATE '2023-12-31'")]
+    fn filter_date(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "and": [
+                            {
+                                "column": "sale date",
+                                "operator": "from",
+                                "value": { "date": "today", "quantity": -1, "duration": "month" }
+                            },
+                            {
+                                "column": "sale date",
+                                "operator": "until",
+                                "value": "2023-12-31"
+                            }
+                        ]
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT * FROM products WHERE \"Price\" > \"Unit cost\"")]
+    #[case::bigquery("bigquery", "SELECT * FROM products WHERE `Price` > `Unit cost`")]
+    fn filter_column_reference(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "products",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "column": "Price",
+                        "operator": "gt",
+                        "value": { "column": "Unit cost" }
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        "SELECT * FROM people WHERE \"Name\" ILIKE '%smith%' ESCAPE '!'"
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "SELECT * FROM people WHERE STRPOS(LOWER(`Name`), LOWER('smith')) > 0"
+    )]
+    fn filter_text(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "people",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "column": "Name",
+                        "operator": "contains",
+                        "value": "smith",
+                        "case_insensitive": true
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        "SELECT * FROM products WHERE \"Code\" ~ '(?p)^[A-Z]{2}\\d+\\Z' AND \"Label\" !~* '(?p)(pika)''s'"
+    )]
+    #[case::bigquery("bigquery", "SELECT * FROM products WHERE REGEXP_CONTAINS(`Code`,'^[A-Z]{2}\\\\d+\\\\z') AND NOT REGEXP_CONTAINS(`Label`,'(?i)(pika)\\'s')")]
+    fn filter_matches(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "products",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "and": [
+                            {
+                                "column": "Code",
+                                "operator": "matches",
+                                "value": "^[A-Z]{2}\\d+\\z"
+                            },
+                            {
+                                "column": "Label",
+                                "operator": "notmatches",
+                                "value": "(?P<brand>pika)'s",
+                                "case_insensitive": true
+                            }
+                        ]
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", true)]
+    #[case::bigquery("bigquery", false)]
+    fn filter_matches_lookahead(#[case] dialect: &str, #[case] supported: bool) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "products",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "column": "Code",
+                        "operator": "matches",
+                        "value": "pika(?!chu)"
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_prql().is_ok(), supported);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        "postgres",
+        "SELECT * FROM sales WHERE \"Price\" BETWEEN 10 AND 20 AND NOT (\"Quantity\" > 0 AND \"Quantity\" < 5)"
+    )]
+    #[case::bigquery(
+        "bigquery",
+        "SELECT * FROM sales WHERE `Price` BETWEEN 10 AND 20 AND NOT (`Quantity` > 0 AND `Quantity` < 5)"
+    )]
+    fn filter_not_between(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "filter",
+                    "condition": {
+                        "and": [
+                            {
+                                "column": "Price",
+                                "operator": "between",
+                                "value": [10, 20]
+                            },
+                            {
+                                "not": {
+                                    "column": "Quantity",
+                                    "operator": "between",
+                                    "value": [0, 5],
+                                    "bounds": "exclusive"
+                                }
+                            }
+                        ]
+                    }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "WITH step_1 AS (SELECT * FROM sales), table_0 AS (SELECT \"City\", PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY \"Price\") AS \"Median price\" FROM step_1 GROUP BY \"City\") SELECT step_1.*, table_0.\"Median price\" FROM step_1 LEFT JOIN table_0 ON step_1.\"City\" = table_0.\"City\" OR step_1.\"City\" IS NULL AND table_0.\"City\" IS NULL", true)]
+    #[case::bigquery("bigquery", "SELECT *, PERCENTILE_CONT(`Price`, 0.5) OVER (PARTITION BY `City`) AS `Median price` FROM sales", true)]
+    fn aggregation_median_keep_yes(#[case] dialect: &str, #[case] sql: &str, #[case] exact: bool) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["City"],
+                    "aggregations": [
+                        { "columns": ["Price"], "newcolumns": ["Median price"], "aggfunction": "median" }
+                    ],
+                    "keepOriginalGranularity": true
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+        assert_eq!(request.is_exact(), exact);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT \"Order id\", STRING_AGG(CAST(\"Product\" AS TEXT), ', ' ORDER BY \"Line\") AS \"Products\" FROM \"order lines\" GROUP BY \"Order id\"")]
+    #[case::bigquery("bigquery", "SELECT `Order id`, STRING_AGG(CAST(`Product` AS STRING), ', ' ORDER BY `Line`) AS `Products` FROM `order lines` GROUP BY `Order id`")]
+    fn aggregation_concat(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "order lines",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Order id"],
+                    "aggregations": [
+                        {
+                            "columns": ["Product"],
+                            "newcolumns": ["Products"],
+                            "aggfunction": { "concat": { "separator": ", " } },
+                            "orderBy": "Line"
+                        }
+                    ]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "WITH step_1 AS (SELECT * FROM prices), table_0 AS (SELECT \"City\", (ARRAY_AGG(\"Price\" ORDER BY \"Day\" DESC NULLS LAST) FILTER (WHERE \"Price\" IS NOT NULL))[1] AS \"Last price\" FROM step_1 GROUP BY \"City\") SELECT step_1.*, table_0.\"Last price\" FROM step_1 LEFT JOIN table_0 ON step_1.\"City\" = table_0.\"City\" OR step_1.\"City\" IS NULL AND table_0.\"City\" IS NULL")]
+    #[case::bigquery("bigquery", "SELECT *, FIRST_VALUE(`Price` IGNORE NULLS) OVER (PARTITION BY `City` ORDER BY `Day` DESC NULLS LAST ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING) AS `Last price` FROM prices")]
+    fn aggregation_last_keep_yes(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "prices",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["City"],
+                    "aggregations": [
+                        { "columns": ["Price"], "newcolumns": ["Last price"], "aggfunction": "last", "orderBy": "Day" }
+                    ],
+                    "keepOriginalGranularity": true
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "WITH step_1 AS (SELECT * FROM sales), table_0 AS (SELECT COUNT(DISTINCT \"Customer\") AS \"Customers\" FROM step_1) SELECT step_1.*, table_0.\"Customers\" FROM step_1 LEFT JOIN table_0 ON true")]
+    #[case::bigquery("bigquery", "WITH step_1 AS (SELECT * FROM sales), table_0 AS (SELECT COUNT(DISTINCT `Customer`) AS `Customers` FROM step_1) SELECT step_1.*, table_0.`Customers` FROM step_1 LEFT JOIN table_0 ON true")]
+    fn aggregation_count_distinct_keep_yes(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "aggregations": [
+                        { "columns": ["Customer"], "newcolumns": ["Customers"], "aggfunction": "count distinct" }
+                    ],
+                    "keepOriginalGranularity": true
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT \"Country\", COALESCE(SUM(\"Amount\"), 0) AS \"Total\", COALESCE(SUM(CASE WHEN \"Channel\" LIKE 'web%' ESCAPE '!' THEN \"Amount\" ELSE NULL END), 0) AS \"Web total\", COALESCE(SUM(CASE WHEN \"Channel\" LIKE 'web%' ESCAPE '!' THEN 1 ELSE 0 END), 0) AS \"Web sales\" FROM sales GROUP BY \"Country\"")]
+    #[case::bigquery("bigquery", "SELECT `Country`, COALESCE(SUM(`Amount`), 0) AS `Total`, COALESCE(SUM(CASE WHEN STARTS_WITH(`Channel`, 'web') THEN `Amount` ELSE NULL END), 0) AS `Web total`, COALESCE(SUM(CASE WHEN STARTS_WITH(`Channel`, 'web') THEN 1 ELSE 0 END), 0) AS `Web sales` FROM sales GROUP BY `Country`")]
+    fn aggregation_conditional(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Total"], "aggfunction": "sum" },
+                        {
+                            "columns": ["Amount"],
+                            "newcolumns": ["Web total"],
+                            "aggfunction": "sum",
+                            "condition": { "column": "Channel", "operator": "startswith", "value": "web" }
+                        },
+                        {
+                            "columns": ["Amount"],
+                            "newcolumns": ["Web sales"],
+                            "aggfunction": "count",
+                            "condition": { "column": "Channel", "operator": "startswith", "value": "web" }
+                        }
+                    ]
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::keep_no(
+        false,
+        "SELECT \"Country\", COALESCE(SUM(\"Amount\"), 0) AS \"Total\" FROM sales GROUP BY \"Country\""
+    )]
+    #[case::keep_yes(
+        true,
+        "SELECT *, COALESCE(SUM(\"Amount\") OVER (PARTITION BY \"Country\"), 0) AS \"Total\" FROM sales"
+    )]
+    fn aggregation_default_empty_sum(#[case] keep_original_granularity: bool, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Total"], "aggfunction": "sum" }
+                    ],
+                    "keepOriginalGranularity": keep_original_granularity
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::keep_no(
+        false,
+        "SELECT \"Country\", SUM(\"Amount\") AS \"Total\" FROM sales GROUP BY \"Country\""
+    )]
+    #[case::keep_yes(
+        true,
+        "SELECT *, SUM(\"Amount\") OVER (PARTITION BY \"Country\") AS \"Total\" FROM sales"
+    )]
+    fn aggregation_null_empty_sum(#[case] keep_original_granularity: bool, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Total"], "aggfunction": "sum" }
+                    ],
+                    "keepOriginalGranularity": keep_original_granularity,
+                    "emptySum": "null"
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[test]
+    fn aggregation_null_empty_conditional_sum() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        {
+                            "columns": ["Amount"],
+                            "newcolumns": ["Web sales"],
+                            "aggfunction": "sum",
+                            "condition": { "column": "Channel", "operator": "eq", "value": "web" }
+                        }
+                    ],
+                    "emptySum": "null"
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.to_sql().unwrap(),
+            r#"WITH table_0 AS (SELECT "Country", CASE WHEN "Channel" = 'web' THEN "Amount" ELSE NULL END AS _expr_0, "Channel", "Amount" FROM sales) SELECT "Country", SUM(_expr_0) AS "Web sales" FROM table_0 GROUP BY "Country""#
+        );
+    }
+
+    #[test]
+    fn aggregation_invalid() {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Total"], "aggfunction": "sum" },
+                        { "columns": ["Amount", "Quantity"], "newcolumns": ["Average"], "aggfunction": "avg" }
+                    ]
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        let error = request.to_prql().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<pipeline::InvalidStep>(),
+            Some(&pipeline::InvalidStep(1))
+        );
+        assert_eq!(
+            error.downcast_ref::<pipeline::steps::aggregate::InvalidAggregation>(),
+            Some(
+                &pipeline::steps::aggregate::InvalidAggregation::MismatchedColumns {
+                    aggregation: 1,
+                    columns: 2,
+                    new_columns: 1
+                }
+            )
+        );
+        assert_eq!(
+            format!("{:#}", error),
+            "Step 1 is invalid: Aggregation 1 has 2 columns but 1 new columns"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres_keep_no("postgres", false, "SELECT DISTINCT \"City\" FROM \"al bums\"")]
+    #[case::postgres_keep_yes("postgres", true, "SELECT * FROM \"al bums\"")]
+    #[case::bigquery_keep_no("bigquery", false, "SELECT DISTINCT `City` FROM `al bums`")]
+    #[case::bigquery_keep_yes("bigquery", true, "SELECT * FROM `al bums`")]
+    fn aggregation_without_aggregations(
+        #[case] dialect: &str,
+        #[case] keep_original_granularity: bool,
+        #[case] sql: &str,
+    ) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "al bums",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["City"],
+                    "aggregations": [],
+                    "keepOriginalGranularity": keep_original_granularity
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT *, COALESCE(SUM(\"Amount\") OVER (PARTITION BY \"Country\" ORDER BY \"Day\" ROWS BETWEEN 6 PRECEDING AND CURRENT ROW), 0) AS \"Weekly total\", VAR_SAMP(\"Amount\") OVER (PARTITION BY \"Country\" ORDER BY \"Day\" ROWS BETWEEN 6 PRECEDING AND CURRENT ROW) AS \"Weekly variance\" FROM sales")]
+    #[case::bigquery("bigquery", "SELECT *, COALESCE(SUM(`Amount`) OVER (PARTITION BY `Country` ORDER BY `Day` ROWS BETWEEN 6 PRECEDING AND CURRENT ROW), 0) AS `Weekly total`, VAR_SAMP(`Amount`) OVER (PARTITION BY `Country` ORDER BY `Day` ROWS BETWEEN 6 PRECEDING AND CURRENT ROW) AS `Weekly variance` FROM sales")]
+    fn aggregation_window_frame(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Weekly total"], "aggfunction": "sum" },
+                        { "columns": ["Amount"], "newcolumns": ["Weekly variance"], "aggfunction": "variance" }
+                    ],
+                    "keepOriginalGranularity": true,
+                    "window": { "frame": "rows", "start": -6, "end": 0, "orderBy": "Day" }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::keep_no(
+        false,
+        r#"SELECT y, VAR_SAMP(x) AS "x variance", SUM(x) AS "x total" FROM points GROUP BY y"#
+    )]
+    #[case::keep_yes(
+        true,
+        r#"SELECT *, VAR_SAMP(x) OVER (PARTITION BY y) AS "x variance", SUM(x) OVER (PARTITION BY y) AS "x total" FROM points"#
+    )]
+    fn aggregation_variance_of_column_x(
+        #[case] keep_original_granularity: bool,
+        #[case] sql: &str,
+    ) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "points",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["y"],
+                    "aggregations": [
+                        { "columns": ["x"], "newcolumns": ["x variance"], "aggfunction": "variance" },
+                        { "columns": ["x"], "newcolumns": ["x total"], "aggfunction": "sum" }
+                    ],
+                    "keepOriginalGranularity": keep_original_granularity,
+                    "emptySum": "null"
+                }
+            ],
+            "dialect": "postgres"
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT *, COALESCE(SUM(\"Amount\") OVER (ORDER BY \"Day\" ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW), 0) AS \"Running total\" FROM sales")]
+    #[case::bigquery("bigquery", "SELECT *, COALESCE(SUM(`Amount`) OVER (ORDER BY `Day` ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW), 0) AS `Running total` FROM sales")]
+    fn aggregation_window_frame_without_groups(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Running total"], "aggfunction": "sum" }
+                    ],
+                    "keepOriginalGranularity": true,
+                    "window": { "end": 0, "orderBy": "Day" }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
+
+    #[rstest]
+    #[case::postgres("postgres", "SELECT *, COALESCE(SUM(\"Amount\") OVER (PARTITION BY \"Country\" ORDER BY \"Day number\" RANGE BETWEEN 6 PRECEDING AND CURRENT ROW), 0) AS \"Weekly total\" FROM sales")]
+    #[case::bigquery("bigquery", "SELECT *, COALESCE(SUM(`Amount`) OVER (PARTITION BY `Country` ORDER BY `Day number` RANGE BETWEEN 6 PRECEDING AND CURRENT ROW), 0) AS `Weekly total` FROM sales")]
+    fn aggregation_range_window_frame(#[case] dialect: &str, #[case] sql: &str) {
+        let request = json!(
+        {
+            "pipeline": [
+                {
+                    "name": "domain",
+                    "domain": "sales",
+                    "table": true,
+                },
+                {
+                    "name": "aggregate",
+                    "on": ["Country"],
+                    "aggregations": [
+                        { "columns": ["Amount"], "newcolumns": ["Weekly total"], "aggfunction": "sum" }
+                    ],
+                    "keepOriginalGranularity": true,
+                    "window": { "frame": "range", "start": -6, "end": 0, "orderBy": "Day number" }
+                }
+            ],
+            "dialect": dialect
+        });
+        let request: Request = serde_json::from_value(request).unwrap();
+        assert_eq!(request.to_sql().unwrap(), sql);
+    }
 }
@@ -1,33 +1,163 @@
 pub(crate) mod steps;
-use crate::translate::{Dialect, ToPrql};
-use anyhow::Result;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlRelation, ToPrqlReplacing};
+use anyhow::{bail, Context, Result};
 
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
+pub(crate) use steps::AbsoluteValueStep;
 pub(crate) use steps::AggregateStep;
+pub(crate) use steps::CompareTextStep;
+pub(crate) use steps::ConvertStep;
+pub(crate) use steps::CustomSqlStep;
+pub(crate) use steps::DissolveStep;
 pub(crate) use steps::DomainStep;
+pub(crate) use steps::DuplicateStep;
 pub(crate) use steps::FilterStep;
+pub(crate) use steps::IfThenElseStep;
+pub(crate) use steps::RollupStep;
+pub(crate) use steps::SimplifyStep;
+pub(crate) use steps::StatisticsStep;
+pub(crate) use steps::TotalsStep;
+pub(crate) use steps::UniqueGroupsStep;
+pub(crate) use steps::WaterfallStep;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Pipeline(pub Vec<PipelineStep>);
 
+/// Context of the errors of a step, with its index in the pipeline
+#[derive(Debug, PartialEq)]
+pub(crate) struct InvalidStep(pub usize);
+
+impl fmt::Display for InvalidStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Step {} is invalid", self.0)
+    }
+}
+
 impl ToPrql for Pipeline {
     fn to_prql(&self, dialect: &Dialect) -> Result<String> {
-        Ok(self
-            .0
-            .iter()
-            .map(|step| step.to_prql(dialect))
-            .collect::<Result<Vec<String>>>()?
-            .join(" | "))
+        // Steps referring to their input relation by name get it bound with `let`
+        // (https://prql-lang.org/book/reference/declarations/variables.html)
+        let mut relations: Vec<String> = vec![];
+        let mut steps: Vec<String> = vec![];
+        let mut columns: Option<Vec<Column>> = None;
+        for (index, step) in self.0.iter().enumerate() {
+            step.validate(columns.as_deref(), dialect)
+                .with_context(|| InvalidStep(index))?;
+            let input = columns.clone();
+            columns = step.output_columns(columns);
+            match (step, step.rendering(dialect)) {
+                // The previous steps are compiled to a SQL subquery of the custom query
+                (PipelineStep::CustomSql(custom_sql), _) if !steps.is_empty() => {
+                    relations.push(steps.join(" | "));
+                    let previous = relations.join("\n\n");
+                    relations = vec![];
+                    steps = vec![custom_sql
+                        .to_prql_wrapping(&previous, dialect)
+                        .with_context(|| InvalidStep(index))?];
+                }
+                (_, Rendering::Relation(_)) if steps.is_empty() => {
+                    bail!("Step {} cannot be the first step of a pipeline", index)
+                }
+                (_, Rendering::Relation(relation)) => {
+                    let input = format!("step_{}", index);
+                    relations.push(format!("let {} = ({})", input, steps.join(" | ")));
+                    steps = vec![relation.to_prql_relation(&input, dialect)?];
+                }
+                (_, Rendering::Piped(step)) => steps.push(step.to_prql(dialect)?),
+                // The previous steps are compiled to a SQL subquery whose columns are
+                // replaced with BigQuery, when they are unknown
+                (_, Rendering::Replacing(step))
+                    if input.is_none()
+                        && !steps.is_empty()
+                        && matches!(dialect, Dialect::BigQuery) =>
+                {
+                    relations.push(steps.join(" | "));
+                    let previous = relations.join("\n\n");
+                    relations = vec![];
+                    steps = vec![step.to_prql_replacing_all(&previous, dialect)?];
+                }
+                (_, Rendering::Replacing(step)) => {
+                    steps.push(step.to_prql_replacing(input.as_deref(), dialect)?)
+                }
+            }
+        }
+        relations.push(steps.join(" | "));
+        Ok(relations.join("\n\n"))
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "name", rename_all = "lowercase")]
-#[enum_dispatch(ToPrql)]
+#[enum_dispatch(Schema)]
 pub enum PipelineStep {
     Domain(DomainStep),
     Aggregate(AggregateStep),
     Filter(FilterStep),
+    IfThenElse(IfThenElseStep),
+    Totals(TotalsStep),
+    Rollup(RollupStep),
+    Waterfall(WaterfallStep),
+    Statistics(StatisticsStep),
+    UniqueGroups(UniqueGroupsStep),
+    Convert(ConvertStep),
+    Duplicate(DuplicateStep),
+    CustomSql(CustomSqlStep),
+    CompareText(CompareTextStep),
+    AbsoluteValue(AbsoluteValueStep),
+    Dissolve(DissolveStep),
+    Simplify(SimplifyStep),
+}
+
+/// How a step is rendered: piped after the previous steps, piped with the columns
+/// of their output, or from the relation they produce bound to a name
+enum Rendering<'a> {
+    Piped(&'a dyn ToPrql),
+    Replacing(&'a dyn ToPrqlReplacing),
+    Relation(&'a dyn ToPrqlRelation),
+}
+
+impl Pipeline {
+    /// Whether the results of the pipeline are exact, some steps computing
+    /// approximations on some dialects
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        self.0.iter().all(|step| step.is_exact(dialect))
+    }
+}
+
+impl PipelineStep {
+    fn is_exact(&self, dialect: &Dialect) -> bool {
+        match self {
+            PipelineStep::Statistics(step) => step.is_exact(dialect),
+            PipelineStep::Aggregate(step) => step.is_exact(dialect),
+            PipelineStep::Totals(step) => step.is_exact(dialect),
+            PipelineStep::Rollup(step) => step.is_exact(dialect),
+            PipelineStep::Dissolve(step) => step.is_exact(dialect),
+            _ => true,
+        }
+    }
+
+    fn rendering(&self, dialect: &Dialect) -> Rendering<'_> {
+        match self {
+            PipelineStep::Domain(step) => Rendering::Piped(step),
+            PipelineStep::Aggregate(step) if step.needs_join(dialect) => Rendering::Relation(step),
+            PipelineStep::Aggregate(step) => Rendering::Piped(step),
+            PipelineStep::Filter(step) => Rendering::Piped(step),
+            PipelineStep::IfThenElse(step) => Rendering::Piped(step),
+            PipelineStep::Totals(step) => Rendering::Relation(step),
+            PipelineStep::Rollup(step) => Rendering::Relation(step),
+            PipelineStep::Waterfall(step) => Rendering::Relation(step),
+            PipelineStep::Statistics(step) => Rendering::Piped(step),
+            PipelineStep::UniqueGroups(step) => Rendering::Piped(step),
+            PipelineStep::Convert(step) => Rendering::Replacing(step),
+            PipelineStep::Duplicate(step) => Rendering::Piped(step),
+            PipelineStep::CustomSql(step) => Rendering::Piped(step),
+            PipelineStep::CompareText(step) => Rendering::Piped(step),
+            PipelineStep::AbsoluteValue(step) => Rendering::Piped(step),
+            PipelineStep::Dissolve(step) => Rendering::Piped(step),
+            PipelineStep::Simplify(step) => Rendering::Replacing(step),
+        }
+    }
 }
@@ -0,0 +1,55 @@
+use crate::pipeline::steps::with_derived_columns;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToSString};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AbsoluteValueStep {
+    column: Column,
+    #[serde(rename = "newColumn")]
+    new_column: Column,
+}
+
+impl ToPrql for AbsoluteValueStep {
+    // `math.abs` is not part of the standard library of PRQL 0.10, we hence rely on s-strings
+    // (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        Ok(format!(
+            r#"derive {{ {} = s"ABS({})" }}"#,
+            self.new_column.to_prql(dialect)?,
+            self.column.to_s_string(dialect)?
+        ))
+    }
+}
+
+impl Schema for AbsoluteValueStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        with_derived_columns(input, [&self.new_column])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres, r#"derive { `abs margin` = s"ABS(\"margin\")" }"#)]
+    #[case::bigquery(Dialect::BigQuery, r#"derive { `abs margin` = s"ABS(`margin`)" }"#)]
+    fn absolutevalue_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "column": "margin",
+            "newColumn": "abs margin"
+        });
+        assert_eq!(
+            serde_json::from_value::<AbsoluteValueStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+}
@@ -1,7 +1,10 @@
+use std::fmt;
 use std::iter::zip;
 
-use crate::translate::{Column, Dialect, ToPrql};
-use anyhow::Result;
+use crate::pipeline::steps::filter::Condition;
+use crate::pipeline::steps::{distinct, with_derived_columns};
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlRelation, ToSString};
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,77 +15,490 @@ pub struct AggregateStep {
     #[serde(rename = "keepOriginalGranularity")]
     #[serde(default)]
     keep_original_granularity: bool,
+    #[serde(rename = "emptySum")]
+    empty_sum: Option<EmptySum>,
+    // Frame of the windows when keeping the original granularity, whole groups by default
+    window: Option<WindowFrame>,
+}
+
+/// Result of sums over groups without values, zero when not given whether the
+/// rows are aggregated or kept
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EmptySum {
+    Null,
+    Zero,
+}
+
+/// Frame of windows ordered by a column, with bounds given as offsets from the
+/// current row, negative ones preceding it, and unbounded when missing. Offsets of
+/// range frames are values of the ordering column, which must then be numeric as
+/// neither Postgres nor BigQuery accept numeric offsets for other columns
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct WindowFrame {
+    #[serde(default)]
+    frame: FrameUnit,
+    start: Option<i64>,
+    end: Option<i64>,
+    #[serde(rename = "orderBy")]
+    order_by: Column,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FrameUnit {
+    #[default]
+    Rows,
+    Range,
+}
+
+impl ToPrql for WindowFrame {
+    // https://prql-lang.org/book/reference/stdlib/transforms/window.html
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let unit = match self.frame {
+            FrameUnit::Rows => "rows",
+            FrameUnit::Range => "range",
+        };
+        let bound = |offset: Option<i64>| offset.map(|offset| offset.to_string());
+        Ok(format!(
+            "sort {{ {} }} | window {}:{}..{}",
+            self.order_by.to_prql(dialect)?,
+            unit,
+            bound(self.start).unwrap_or_default(),
+            bound(self.end).unwrap_or_default()
+        ))
+    }
+}
+
+impl ToSString for WindowFrame {
+    // Ordering and frame of the OVER clauses of s-strings
+    fn to_s_string(&self, dialect: &Dialect) -> Result<String> {
+        let unit = match self.frame {
+            FrameUnit::Rows => "ROWS",
+            FrameUnit::Range => "RANGE",
+        };
+        let bound = |offset: Option<i64>, unbounded: &str| match offset {
+            None => format!("UNBOUNDED {}", unbounded),
+            Some(0) => "CURRENT ROW".to_string(),
+            Some(offset) if offset < 0 => format!("{} PRECEDING", -offset),
+            Some(offset) => format!("{} FOLLOWING", offset),
+        };
+        Ok(format!(
+            "ORDER BY {{{}}} {} BETWEEN {} AND {}",
+            self.order_by.to_prql(dialect)?,
+            unit,
+            bound(self.start, "PRECEDING"),
+            bound(self.end, "FOLLOWING")
+        ))
+    }
 }
 
 impl ToPrql for AggregateStep {
     // https://prql-lang.org/book/reference/stdlib/transforms/aggregate.html
     fn to_prql(&self, dialect: &Dialect) -> Result<String> {
-        match (self.on.len(), self.keep_original_granularity) {
-            (0, false) => Ok(format!(
-                "aggregate {{ {} }}",
-                self.aggregations
-                    .iter()
-                    .map(|agg| agg.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", ")
-            )),
-            (0, true) => Ok(format!(
-                "derive {{ {} }}",
-                self.aggregations
-                    .iter()
-                    .map(|agg| agg.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", ")
-            )),
-            (_, false) => Ok(format!(
-                "group {{ {} }} ( aggregate {{ {} }} )",
-                self.on
-                    .iter()
-                    .map(|col| col.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", "),
-                self.aggregations
-                    .iter()
-                    .map(|agg| agg.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", ")
-            )),
-            (_, true) => Ok(format!(
-                // https://prql-lang.org/book/reference/stdlib/transforms/window.html
-                "group {{ {} }} ( window rows:.. ( derive {{ {} }} ) )",
-                self.on
-                    .iter()
-                    .map(|col| col.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", "),
-                self.aggregations
-                    .iter()
-                    .map(|agg| agg.to_prql(dialect))
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", ")
-            )),
+        if self.aggregations.is_empty() {
+            return self.distinct_groups(dialect);
+        }
+        if !self.keep_original_granularity {
+            return grouped_aggregation(
+                &self.on,
+                &self.aggregations,
+                self.empty_sum.as_ref(),
+                dialect,
+            );
+        }
+        if self.needs_join(dialect) {
+            bail!("The aggregate step can only be rendered from a named input relation")
+        }
+        let derive = format!(
+            "derive {{ {} }}",
+            self.aggregations
+                .iter()
+                .map(|agg| {
+                    agg.to_prql_window(
+                        &self.on,
+                        self.window.as_ref(),
+                        self.empty_sum.as_ref(),
+                        dialect,
+                    )
+                })
+                .collect::<Result<Vec<String>>>()?
+                .join(", ")
+        );
+        let window = match &self.window {
+            // https://prql-lang.org/book/reference/stdlib/transforms/window.html
+            None => "window rows:..".to_string(),
+            Some(frame) => frame.to_prql(dialect)?,
+        };
+        let on = self
+            .on
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+        // Windows are sorted within groups, even without group columns, as sorting
+        // the relation itself would also order the rows of the result
+        match (on.as_str(), &self.window) {
+            ("", None) => Ok(derive),
+            ("", Some(_)) => Ok(format!("group {{}} ( {} ( {} ) )", window, derive)),
+            _ => Ok(format!("group {{ {} }} ( {} ( {} ) )", on, window, derive)),
+        }
+    }
+}
+
+impl ToPrqlRelation for AggregateStep {
+    // Some aggregations cannot be computed over windows, they are hence computed
+    // for each group and joined back to the rows of the group
+    fn to_prql_relation(&self, input: &str, dialect: &Dialect) -> Result<String> {
+        if self.window.is_some() {
+            bail!("Window frames cannot be applied to aggregations joined back to the rows, which some aggregations of the step require with this dialect")
+        }
+        let condition = match self.on.len() {
+            0 => "true".to_string(),
+            _ => self
+                .on
+                .iter()
+                .map(|col| {
+                    let col = col.to_prql(dialect)?;
+                    Ok(format!(
+                        "(this.{} == that.{} || (this.{} == null && that.{} == null))",
+                        col, col, col, col
+                    ))
+                })
+                .collect::<Result<Vec<String>>>()?
+                .join(" && "),
+        };
+        let new_columns = self
+            .aggregations
+            .iter()
+            .flat_map(|agg| &agg.new_columns)
+            .map(|col| Ok(format!("__aggregates.{}", col.to_prql(dialect)?)))
+            .collect::<Result<Vec<String>>>()?;
+        Ok(format!(
+            "from {} | join side:left (__aggregates = (from {} | {})) ({}) | select {{ {}.*, {} }}",
+            input,
+            input,
+            grouped_aggregation(
+                &self.on,
+                &self.aggregations,
+                self.empty_sum.as_ref(),
+                dialect,
+            )?,
+            condition,
+            input,
+            new_columns.join(", ")
+        ))
+    }
+}
+
+impl Schema for AggregateStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        let new_columns = self.aggregations.iter().flat_map(|agg| &agg.new_columns);
+        match self.keep_original_granularity {
+            true => with_derived_columns(input, new_columns),
+            false => with_derived_columns(Some(self.on.clone()), new_columns),
+        }
+    }
+
+    fn validate(&self, input: Option<&[Column]>, dialect: &Dialect) -> Result<()> {
+        if let Some(frame) = &self.window {
+            self.validate_frame(frame, dialect)?;
+        }
+        let mut new_names = vec![];
+        for (index, agg) in self.aggregations.iter().enumerate() {
+            if agg.columns.len() != agg.new_columns.len() {
+                return Err(InvalidAggregation::MismatchedColumns {
+                    aggregation: index,
+                    columns: agg.columns.len(),
+                    new_columns: agg.new_columns.len(),
+                }
+                .into());
+            }
+            for new_column in &agg.new_columns {
+                if self.on.contains(new_column) {
+                    return Err(InvalidAggregation::ShadowedGroupColumn {
+                        aggregation: index,
+                        column: new_column.clone(),
+                    }
+                    .into());
+                }
+                // Columns derived next to those of the input, e.g. joined back to them,
+                // cannot replace one of them without duplicating it
+                if self.keep_original_granularity
+                    && input.is_some_and(|input| input.contains(new_column))
+                {
+                    return Err(InvalidAggregation::ShadowedInputColumn {
+                        aggregation: index,
+                        column: new_column.clone(),
+                    }
+                    .into());
+                }
+                if new_names.contains(&new_column) {
+                    return Err(InvalidAggregation::DuplicateNewColumn {
+                        aggregation: index,
+                        column: new_column.clone(),
+                    }
+                    .into());
+                }
+                new_names.push(new_column);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Problem of an aggregation of the aggregate step, with its index in the step
+#[derive(Debug, PartialEq)]
+pub(crate) enum InvalidAggregation {
+    MismatchedColumns {
+        aggregation: usize,
+        columns: usize,
+        new_columns: usize,
+    },
+    DuplicateNewColumn {
+        aggregation: usize,
+        column: Column,
+    },
+    ShadowedGroupColumn {
+        aggregation: usize,
+        column: Column,
+    },
+    ShadowedInputColumn {
+        aggregation: usize,
+        column: Column,
+    },
+    Unframeable {
+        aggregation: usize,
+    },
+}
+
+impl fmt::Display for InvalidAggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidAggregation::MismatchedColumns {
+                aggregation,
+                columns,
+                new_columns,
+            } => write!(
+                f,
+                "Aggregation {} has {} columns but {} new columns",
+                aggregation, columns, new_columns
+            ),
+            InvalidAggregation::DuplicateNewColumn {
+                aggregation,
+                column,
+            } => write!(
+                f,
+                "Aggregation {} outputs {} which is already an output of the step",
+                aggregation, column.0
+            ),
+            InvalidAggregation::ShadowedGroupColumn {
+                aggregation,
+                column,
+            } => write!(
+                f,
+                "Aggregation {} outputs {} which is a group column",
+                aggregation, column.0
+            ),
+            InvalidAggregation::ShadowedInputColumn {
+                aggregation,
+                column,
+            } => write!(
+                f,
+                "Aggregation {} outputs {} which is already a column of the rows it is added to",
+                aggregation, column.0
+            ),
+            InvalidAggregation::Unframeable { aggregation } => write!(
+                f,
+                "Aggregation {} cannot be computed over a window frame with this dialect",
+                aggregation
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidAggregation {}
+
+impl AggregateStep {
+    fn validate_frame(&self, frame: &WindowFrame, dialect: &Dialect) -> Result<()> {
+        if !self.keep_original_granularity {
+            bail!("Window frames are only used when keeping the original granularity")
+        }
+        if self.aggregations.is_empty() {
+            bail!("Window frames are only used to compute aggregations")
+        }
+        if let (Some(start), Some(end)) = (frame.start, frame.end) {
+            if start > end {
+                bail!("The window frame starts after its end")
+            }
+        }
+        for (index, agg) in self.aggregations.iter().enumerate() {
+            if !agg.function.is_frameable(dialect) {
+                return Err(InvalidAggregation::Unframeable { aggregation: index }.into());
+            }
         }
+        Ok(())
+    }
+
+    // Without aggregations, the rows are kept as is or reduced to their distinct groups
+    fn distinct_groups(&self, dialect: &Dialect) -> Result<String> {
+        if self.keep_original_granularity {
+            return Ok("derive {}".to_string());
+        }
+        if self.on.is_empty() {
+            bail!("The aggregate step needs group columns or aggregations")
+        }
+        distinct(&self.on, dialect)
+    }
+
+    /// Whether keeping the original granularity requires joining aggregations
+    /// back to the rows, as some of them cannot be computed over windows
+    pub fn needs_join(&self, dialect: &Dialect) -> bool {
+        self.keep_original_granularity
+            && self
+                .aggregations
+                .iter()
+                .any(|agg| !agg.function.is_windowable(dialect))
+    }
+
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        let windowed = self.keep_original_granularity && !self.needs_join(dialect);
+        self.aggregations
+            .iter()
+            .all(|agg| agg.function.is_exact(dialect, windowed))
+    }
+}
+
+/// Aggregates the whole relation, or each group of `on` columns if any
+pub(crate) fn grouped_aggregation(
+    on: &[Column],
+    aggregations: &[Aggregation],
+    empty_sum: Option<&EmptySum>,
+    dialect: &Dialect,
+) -> Result<String> {
+    let values = aggregations
+        .iter()
+        .map(|agg| agg.derived_values(empty_sum, dialect))
+        .collect::<Result<Vec<Vec<String>>>>()?
+        .concat();
+    let aggregate = group_aggregate(
+        on,
+        &aggregations
+            .iter()
+            .map(|agg| agg.render(None, empty_sum, dialect))
+            .collect::<Result<Vec<String>>>()?,
+        dialect,
+    )?;
+    match values.is_empty() {
+        true => Ok(aggregate),
+        false => Ok(format!(
+            "derive {{ {} }} | {}",
+            values.join(", "),
+            aggregate
+        )),
+    }
+}
+
+/// Computes the given PRQL aggregate expressions over the whole relation, or
+/// over each group of `on` columns if any
+pub(crate) fn group_aggregate(
+    on: &[Column],
+    aggregates: &[String],
+    dialect: &Dialect,
+) -> Result<String> {
+    let aggregate = format!("aggregate {{ {} }}", aggregates.join(", "));
+    if on.is_empty() {
+        return Ok(aggregate);
     }
+    Ok(format!(
+        "group {{ {} }} ( {} )",
+        on.iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?
+            .join(", "),
+        aggregate
+    ))
+}
+
+/// Applies an SQL function missing from PRQL to a column, given as PRQL, followed by
+/// the given clauses, e.g. an OVER one. S-strings can only interpolate names, so
+/// expressions have to be derived into columns beforehand
+pub(crate) fn sql_function(function: &str, column: &str, clauses: &str) -> String {
+    format!(r#"s"{}({{{}}}){}""#, function, column, clauses)
+}
+
+/// Whether aggregations computed for each group are exact, some functions being
+/// approximated on some dialects
+pub(crate) fn aggregations_are_exact(aggregations: &[Aggregation], dialect: &Dialect) -> bool {
+    aggregations
+        .iter()
+        .all(|agg| agg.function.is_exact(dialect, false))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct Aggregation {
+pub(crate) struct Aggregation {
     pub columns: Vec<Column>,
     #[serde(rename = "newcolumns")]
     pub new_columns: Vec<Column>,
     #[serde(rename = "aggfunction")]
     pub function: AggregationFn,
+    // Order of the values for the first, last and concat aggregations
+    #[serde(rename = "orderBy")]
+    pub order_by: Option<Column>,
+    // Only the rows matching the condition are aggregated
+    pub condition: Option<Condition>,
 }
 
 impl ToPrql for Aggregation {
     fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        self.render(None, None, dialect)
+    }
+}
+
+impl Aggregation {
+    /// Renders the aggregation over windows partitioned by the given columns,
+    /// with the given frame or over whole partitions
+    pub fn to_prql_window(
+        &self,
+        partition: &[Column],
+        frame: Option<&WindowFrame>,
+        empty_sum: Option<&EmptySum>,
+        dialect: &Dialect,
+    ) -> Result<String> {
+        self.render(Some(&Over { partition, frame }), empty_sum, dialect)
+    }
+
+    fn render(
+        &self,
+        window: Option<&Over>,
+        empty_sum: Option<&EmptySum>,
+        dialect: &Dialect,
+    ) -> Result<String> {
+        if self.columns.len() != self.new_columns.len() {
+            bail!(
+                "An aggregation has {} columns but {} new columns",
+                self.columns.len(),
+                self.new_columns.len()
+            )
+        }
+        let derives_values = window.is_none() && self.derives_values(empty_sum);
         Ok(zip(&self.columns, &self.new_columns)
             .map(|(col, new_col)| {
+                let (col, condition) = match derives_values {
+                    true => (&Self::values_column(new_col), None),
+                    false => (col, self.condition.as_ref()),
+                };
                 Ok(format!(
-                    "{} = {} {}",
+                    "{} = {}",
                     new_col.to_prql(dialect)?,
-                    self.function.to_prql(dialect)?,
-                    col.to_prql(dialect).unwrap(),
+                    self.function.aggregate(
+                        col,
+                        self.order_by.as_ref(),
+                        condition,
+                        empty_sum,
+                        window,
+                        dialect
+                    )?,
                 ))
             })
             .collect::<Result<Vec<String>>>()?
@@ -90,9 +506,52 @@ impl ToPrql for Aggregation {
     }
 }
 
+impl Aggregation {
+    // Sums of the matching rows which are null when empty are computed with SQL,
+    // which cannot be given the PRQL condition, hence from values derived beforehand
+    fn derives_values(&self, empty_sum: Option<&EmptySum>) -> bool {
+        matches!(self.function, AggregationFn::Sum)
+            && matches!(empty_sum, Some(EmptySum::Null))
+            && self.condition.is_some()
+    }
+
+    fn values_column(new_column: &Column) -> Column {
+        Column(format!("__{}_values", new_column.0))
+    }
+
+    /// Derivations of the values aggregated for each group, when they cannot be
+    /// computed within the aggregation
+    fn derived_values(
+        &self,
+        empty_sum: Option<&EmptySum>,
+        dialect: &Dialect,
+    ) -> Result<Vec<String>> {
+        let condition = match &self.condition {
+            Some(condition) if self.derives_values(empty_sum) => condition.to_prql(dialect)?,
+            _ => return Ok(vec![]),
+        };
+        zip(&self.columns, &self.new_columns)
+            .map(|(col, new_col)| {
+                Ok(format!(
+                    "{} = (case [{} => {}])",
+                    Self::values_column(new_col).to_prql(dialect)?,
+                    condition,
+                    col.to_prql(dialect)?
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Window over which an aggregation is computed
+pub(crate) struct Over<'a> {
+    partition: &'a [Column],
+    frame: Option<&'a WindowFrame>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
-enum AggregationFn {
+pub(crate) enum AggregationFn {
     Min,
     Max,
     Count,
@@ -102,23 +561,261 @@ enum AggregationFn {
     CountDistinct,
     First,
     Last,
+    Median,
+    Stddev,
+    Variance,
+    // Given as {"percentile": 0.9}
+    Percentile(f64),
+    Mode,
+    // Given as {"concat": {"separator": ", ", "distinct": true}}, the values being
+    // concatenated in the order of the orderBy column of the aggregation
+    Concat {
+        separator: String,
+        #[serde(default)]
+        distinct: bool,
+    },
 }
 
-impl ToPrql for AggregationFn {
-    fn to_prql(&self, _dialect: &Dialect) -> Result<String> {
-        Ok(match self {
-            AggregationFn::Min => "min",
-            AggregationFn::Max => "max",
-            AggregationFn::Count => "count",
-            AggregationFn::Avg => "avg",
-            AggregationFn::Sum => "sum",
-            AggregationFn::CountDistinct => "count_distinct",
-            // https://prql-lang.org/book/reference/stdlib/transforms/aggregate.html#admonition-note
-            // Those two are not implemented in PRQL, but we can use min and max
-            AggregationFn::First => "min",
-            AggregationFn::Last => "max",
+impl AggregationFn {
+    // Functions missing from PRQL are rendered with s-strings, to which PRQL does not
+    // add the OVER clause of windows (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn aggregate(
+        &self,
+        column: &Column,
+        order_by: Option<&Column>,
+        condition: Option<&Condition>,
+        empty_sum: Option<&EmptySum>,
+        window: Option<&Over>,
+        dialect: &Dialect,
+    ) -> Result<String> {
+        let partition = window.map(|window| window.partition);
+        let frame = window
+            .and_then(|window| window.frame)
+            .map(|frame| frame.to_s_string(dialect))
+            .transpose()?;
+        let condition = condition
+            .map(|condition| condition.to_prql(dialect))
+            .transpose()?;
+        // Rows not matching the condition are aggregated as nulls
+        let column = match &condition {
+            None => column.to_prql(dialect)?,
+            Some(_) if !self.is_native() => {
+                bail!("Conditions are only supported with min, max, count, avg, sum, count distinct and stddev aggregations")
+            }
+            Some(condition) => format!("(case [{} => {}])", condition, column.to_prql(dialect)?),
+        };
+        let partition_by = match partition {
+            Some(partition) if !partition.is_empty() => Some(format!(
+                "PARTITION BY {}",
+                partition
+                    .iter()
+                    .map(|col| Ok(format!("{{{}}}", col.to_prql(dialect)?)))
+                    .collect::<Result<Vec<String>>>()?
+                    .join(", ")
+            )),
+            _ => None,
+        };
+        let over = |ordering: Option<String>| -> Result<String> {
+            if ordering.is_some() && frame.is_some() {
+                bail!("This aggregation has its own ordering and cannot be computed over a window frame")
+            }
+            Ok(match partition {
+                None => "".to_string(),
+                Some(_) => format!(
+                    " OVER ({})",
+                    partition_by
+                        .iter()
+                        .cloned()
+                        .chain(ordering.or(frame.clone()))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ),
+            })
+        };
+        if partition.is_some() && !self.is_windowable(dialect) {
+            bail!("This aggregation cannot be computed over a window with this dialect")
+        }
+        if frame.is_some() && !self.is_frameable(dialect) {
+            bail!("This aggregation cannot be computed over a window frame with this dialect")
+        }
+        // Null values are skipped, and rows without an order come last
+        let ordered = |descending: bool| -> Result<String> {
+            match order_by {
+                Some(order_by) => Ok(format!(
+                    "ORDER BY {{{}}}{} NULLS LAST",
+                    order_by.to_prql(dialect)?,
+                    if descending { " DESC" } else { "" }
+                )),
+                None => bail!("The first and last aggregations need an orderBy column"),
+            }
+        };
+        if order_by.is_some()
+            && !matches!(
+                self,
+                AggregationFn::First | AggregationFn::Last | AggregationFn::Concat { .. }
+            )
+        {
+            bail!("Only the first, last and concat aggregations can be ordered")
+        }
+        Ok(match (self, dialect) {
+            (AggregationFn::Min, _) => format!("min {}", column),
+            (AggregationFn::Max, _) => format!("max {}", column),
+            // PRQL counts all rows whatever its argument
+            (AggregationFn::Count, _) => match condition {
+                None => format!("count {}", column),
+                Some(condition) => format!("sum (case [{} => 1, true => 0])", condition),
+            },
+            (AggregationFn::Avg, _) => format!("avg {}", column),
+            // PRQL coalesces sums of plain aggregates to zero, but not sums over windows,
+            // which are hence coalesced unless null is asked for
+            (AggregationFn::Sum, _) => match (empty_sum, partition) {
+                (Some(EmptySum::Zero) | None, Some(_)) => format!("(sum {}) ?? 0", column),
+                (Some(EmptySum::Null), None) => sql_function("SUM", &column, ""),
+                _ => format!("sum {}", column),
+            },
+            (AggregationFn::CountDistinct, _) => format!("count_distinct {}", column),
+            (AggregationFn::First | AggregationFn::Last, Dialect::Postgres) => format!(
+                r#"s"(ARRAY_AGG({{{}}} {}) FILTER (WHERE {{{}}} IS NOT NULL))[1]""#,
+                column,
+                ordered(matches!(self, AggregationFn::Last))?,
+                column
+            ),
+            (AggregationFn::First | AggregationFn::Last, Dialect::BigQuery) => match partition {
+                None => format!(
+                    r#"s"ARRAY_AGG({{{}}} IGNORE NULLS {} LIMIT 1)[SAFE_OFFSET(0)]""#,
+                    column,
+                    ordered(matches!(self, AggregationFn::Last))?
+                ),
+                Some(_) => format!(
+                    r#"s"FIRST_VALUE({{{}}} IGNORE NULLS){}""#,
+                    column,
+                    over(Some(format!(
+                        "{} ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING",
+                        ordered(matches!(self, AggregationFn::Last))?
+                    )))?
+                ),
+            },
+            (AggregationFn::Stddev, _) => format!("stddev {}", column),
+            (AggregationFn::Variance, _) => sql_function("VAR_SAMP", &column, &over(None)?),
+            (AggregationFn::Median | AggregationFn::Percentile(_), _) => {
+                // Medians are the 0.5 percentiles
+                let p = match self {
+                    AggregationFn::Percentile(p) => *p,
+                    _ => 0.5,
+                };
+                if !(0.0..=1.0).contains(&p) {
+                    bail!(
+                        "Invalid percentile {}: expected a number between 0 and 1",
+                        p
+                    )
+                }
+                match (dialect, partition) {
+                    (Dialect::Postgres, _) => format!(
+                        r#"s"PERCENTILE_CONT({}) WITHIN GROUP (ORDER BY {{{}}})""#,
+                        p, column
+                    ),
+                    // The only percentile aggregate function of BigQuery is approximate
+                    (Dialect::BigQuery, None) => format!(
+                        r#"s"APPROX_QUANTILES({{{}}}, 1000)[OFFSET({})]""#,
+                        column,
+                        (p * 1000.0).round()
+                    ),
+                    (Dialect::BigQuery, Some(_)) => {
+                        format!(
+                            r#"s"PERCENTILE_CONT({{{}}}, {}){}""#,
+                            column,
+                            p,
+                            over(None)?
+                        )
+                    }
+                }
+            }
+            (AggregationFn::Mode, Dialect::Postgres) => {
+                format!(r#"s"MODE() WITHIN GROUP (ORDER BY {{{}}})""#, column)
+            }
+            (AggregationFn::Mode, Dialect::BigQuery) => {
+                format!(r#"s"APPROX_TOP_COUNT({{{}}}, 1)[OFFSET(0)].value""#, column)
+            }
+            // Values are cast to text, and must be ordered by their cast with DISTINCT
+            (
+                AggregationFn::Concat {
+                    separator,
+                    distinct,
+                },
+                _,
+            ) => {
+                let text = match dialect {
+                    Dialect::Postgres => "TEXT",
+                    Dialect::BigQuery => "STRING",
+                };
+                let value = format!("CAST({{{}}} AS {})", column, text);
+                let order_by = match order_by {
+                    None => "".to_string(),
+                    Some(order_by) if order_by.to_prql(dialect)? == column => {
+                        format!(" ORDER BY {}", value)
+                    }
+                    Some(_) if *distinct => {
+                        bail!("Distinct values can only be concatenated in the order of the values")
+                    }
+                    Some(order_by) => format!(" ORDER BY {{{}}}", order_by.to_prql(dialect)?),
+                };
+                format!(
+                    r#"s"STRING_AGG({}{}, {}{})""#,
+                    if *distinct { "DISTINCT " } else { "" },
+                    value,
+                    separator.to_s_string(dialect)?,
+                    order_by
+                )
+            }
+        })
+    }
+
+    // Whether the function is available in PRQL, taking any expression as argument
+    fn is_native(&self) -> bool {
+        matches!(
+            self,
+            AggregationFn::Min
+                | AggregationFn::Max
+                | AggregationFn::Count
+                | AggregationFn::Avg
+                | AggregationFn::Sum
+                | AggregationFn::CountDistinct
+                | AggregationFn::Stddev
+        )
+    }
+
+    /// Whether the function can be computed over a window, ordered-set and
+    /// approximate aggregate functions being only available as plain aggregates
+    fn is_windowable(&self, dialect: &Dialect) -> bool {
+        match (self, dialect) {
+            (AggregationFn::Median | AggregationFn::Percentile(_), Dialect::Postgres) => false,
+            (AggregationFn::Median | AggregationFn::Percentile(_), Dialect::BigQuery) => true,
+            (AggregationFn::First | AggregationFn::Last, Dialect::Postgres) => false,
+            // ORDER BY and DISTINCT are not allowed in the arguments of window functions
+            (
+                AggregationFn::CountDistinct | AggregationFn::Mode | AggregationFn::Concat { .. },
+                _,
+            ) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the function can be computed over a window frame, first and last
+    /// values having their own ordering and BigQuery percentiles only accepting partitions
+    fn is_frameable(&self, dialect: &Dialect) -> bool {
+        match (self, dialect) {
+            (AggregationFn::First | AggregationFn::Last, _) => false,
+            (AggregationFn::Median | AggregationFn::Percentile(_), Dialect::BigQuery) => false,
+            _ => self.is_windowable(dialect),
+        }
+    }
+
+    fn is_exact(&self, dialect: &Dialect, windowed: bool) -> bool {
+        match (self, dialect) {
+            (AggregationFn::Median | AggregationFn::Percentile(_), Dialect::BigQuery) => windowed,
+            (AggregationFn::Mode, Dialect::BigQuery) => false,
+            _ => true,
         }
-        .to_string())
     }
 }
 
@@ -183,7 +880,7 @@ mod tests {
                 .unwrap()
                 .to_prql(&dialect)
                 .unwrap(),
-            "derive { `Price_sum` = sum `Price`, `Somme des quantités` = sum `Quantity` }"
+            "derive { `Price_sum` = (sum `Price`) ?? 0, `Somme des quantités` = (sum `Quantity`) ?? 0 }"
         );
     }
 
@@ -242,7 +939,527 @@ mod tests {
                 .unwrap()
                 .to_prql(&dialect)
                 .unwrap(),
-            "group { `col`, `other col` } ( window rows:.. ( derive { `Price_sum` = sum `Price`, `Somme des quantités` = sum `Quantity` } ) )"
+            "group { `col`, `other col` } ( window rows:.. ( derive { `Price_sum` = (sum `Price`) ?? 0, `Somme des quantités` = (sum `Quantity`) ?? 0 } ) )"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"group { `city` } ( aggregate { `median` = s"PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY {`price`})", `p90` = s"PERCENTILE_CONT(0.9) WITHIN GROUP (ORDER BY {`price`})", `stddev` = stddev `price`, `variance` = s"VAR_SAMP({`price`})", `mode` = s"MODE() WITHIN GROUP (ORDER BY {`price`})" } )"#,
+        true
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"group { `city` } ( aggregate { `median` = s"APPROX_QUANTILES({`price`}, 1000)[OFFSET(500)]", `p90` = s"APPROX_QUANTILES({`price`}, 1000)[OFFSET(900)]", `stddev` = stddev `price`, `variance` = s"VAR_SAMP({`price`})", `mode` = s"APPROX_TOP_COUNT({`price`}, 1)[OFFSET(0)].value" } )"#,
+        false
+    )]
+    fn statistical_functions(#[case] dialect: Dialect, #[case] prql: &str, #[case] exact: bool) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["median"], "aggfunction": "median" },
+                { "columns": ["price"], "newcolumns": ["p90"], "aggfunction": { "percentile": 0.9 } },
+                { "columns": ["price"], "newcolumns": ["stddev"], "aggfunction": "stddev" },
+                { "columns": ["price"], "newcolumns": ["variance"], "aggfunction": "variance" },
+                { "columns": ["price"], "newcolumns": ["mode"], "aggfunction": "mode" }
+            ]
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        assert_eq!(step.to_prql(&dialect).unwrap(), prql);
+        assert_eq!(step.is_exact(&dialect), exact);
+    }
+
+    #[test]
+    fn statistical_functions_with_keep_columns() {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["median"], "aggfunction": "median" },
+                { "columns": ["price"], "newcolumns": ["variance"], "aggfunction": "variance" }
+            ],
+            "keepOriginalGranularity": true
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        // Percentiles can be computed over windows with BigQuery, and exactly
+        assert!(!step.needs_join(&Dialect::BigQuery));
+        assert!(step.is_exact(&Dialect::BigQuery));
+        assert_eq!(
+            step.to_prql(&Dialect::BigQuery).unwrap(),
+            r#"group { `city` } ( window rows:.. ( derive { `median` = s"PERCENTILE_CONT({`price`}, 0.5) OVER (PARTITION BY {`city`})", `variance` = s"VAR_SAMP({`price`}) OVER (PARTITION BY {`city`})" } ) )"#
+        );
+        // but not with Postgres
+        assert!(step.needs_join(&Dialect::Postgres));
+        assert!(step.to_prql(&Dialect::Postgres).is_err());
+        assert_eq!(
+            step.to_prql_relation("step_1", &Dialect::Postgres).unwrap(),
+            r#"from step_1 | join side:left (__aggregates = (from step_1 | group { `city` } ( aggregate { `median` = s"PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY {`price`})", `variance` = s"VAR_SAMP({`price`})" } ))) ((this.`city` == that.`city` || (this.`city` == null && that.`city` == null))) | select { step_1.*, __aggregates.`median`, __aggregates.`variance` }"#
         );
     }
+
+    #[rstest]
+    #[case::negative(-0.1)]
+    #[case::above_one(1.5)]
+    fn invalid_percentile(#[case] percentile: f64) {
+        let input = json!({
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["p"], "aggfunction": { "percentile": percentile } }
+            ]
+        });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"group { `order` } ( aggregate { `products` = s"STRING_AGG(CAST({`product`} AS TEXT), ', ' ORDER BY {`line`})", `categories` = s"STRING_AGG(DISTINCT CAST({`category`} AS TEXT), ' | ' ORDER BY CAST({`category`} AS TEXT))" } )"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"group { `order` } ( aggregate { `products` = s"STRING_AGG(CAST({`product`} AS STRING), ', ' ORDER BY {`line`})", `categories` = s"STRING_AGG(DISTINCT CAST({`category`} AS STRING), ' | ' ORDER BY CAST({`category`} AS STRING))" } )"#
+    )]
+    fn concat(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "on": ["order"],
+            "aggregations": [
+                {
+                    "columns": ["product"],
+                    "newcolumns": ["products"],
+                    "aggfunction": { "concat": { "separator": ", " } },
+                    "orderBy": "line"
+                },
+                {
+                    "columns": ["category"],
+                    "newcolumns": ["categories"],
+                    "aggfunction": { "concat": { "separator": " | ", "distinct": true } },
+                    "orderBy": "category"
+                }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn concat_distinct_ordered_by_other_column() {
+        let input = json!({
+            "aggregations": [
+                {
+                    "columns": ["category"],
+                    "newcolumns": ["categories"],
+                    "aggfunction": { "concat": { "separator": ", ", "distinct": true } },
+                    "orderBy": "line"
+                }
+            ]
+        });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"group { `city` } ( aggregate { `first price` = s"(ARRAY_AGG({`price`} ORDER BY {`day`} NULLS LAST) FILTER (WHERE {`price`} IS NOT NULL))[1]", `last price` = s"(ARRAY_AGG({`price`} ORDER BY {`day`} DESC NULLS LAST) FILTER (WHERE {`price`} IS NOT NULL))[1]" } )"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"group { `city` } ( aggregate { `first price` = s"ARRAY_AGG({`price`} IGNORE NULLS ORDER BY {`day`} NULLS LAST LIMIT 1)[SAFE_OFFSET(0)]", `last price` = s"ARRAY_AGG({`price`} IGNORE NULLS ORDER BY {`day`} DESC NULLS LAST LIMIT 1)[SAFE_OFFSET(0)]" } )"#
+    )]
+    fn first_last(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["first price"], "aggfunction": "first", "orderBy": "day" },
+                { "columns": ["price"], "newcolumns": ["last price"], "aggfunction": "last", "orderBy": "day" }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn first_last_keep_granularity() {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["last price"], "aggfunction": "last", "orderBy": "day" }
+            ],
+            "keepOriginalGranularity": true
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        assert!(!step.needs_join(&Dialect::BigQuery));
+        assert_eq!(
+            step.to_prql(&Dialect::BigQuery).unwrap(),
+            r#"group { `city` } ( window rows:.. ( derive { `last price` = s"FIRST_VALUE({`price`} IGNORE NULLS) OVER (PARTITION BY {`city`} ORDER BY {`day`} DESC NULLS LAST ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING)" } ) )"#
+        );
+        assert!(step.needs_join(&Dialect::Postgres));
+    }
+
+    #[rstest]
+    #[case::first_without_order(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "first" }))]
+    #[case::last_without_order(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "last" }))]
+    #[case::ordered_median(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "median", "orderBy": "day" }))]
+    #[case::ordered_sum(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "sum", "orderBy": "day" }))]
+    fn invalid_order(#[case] aggregation: serde_json::Value) {
+        let input = json!({ "aggregations": [aggregation] });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        assert!(step.to_prql(&Dialect::Postgres).is_err());
+        assert!(step.to_prql(&Dialect::BigQuery).is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn count_distinct_keep_granularity(#[case] dialect: Dialect) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["customer"], "newcolumns": ["customers"], "aggfunction": "count distinct" }
+            ],
+            "keepOriginalGranularity": true
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        // COUNT(DISTINCT ...) OVER (...) is invalid, so groups are joined back
+        assert!(step.needs_join(&dialect));
+        assert!(step.to_prql(&dialect).is_err());
+        assert_eq!(
+            step.to_prql_relation("step_1", &dialect).unwrap(),
+            "from step_1 | join side:left (__aggregates = (from step_1 | group { `city` } ( aggregate { `customers` = count_distinct `customer` } ))) ((this.`city` == that.`city` || (this.`city` == null && that.`city` == null))) | select { step_1.*, __aggregates.`customers` }"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn conditional(#[case] dialect: Dialect) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                {
+                    "columns": ["price"],
+                    "newcolumns": ["web sales"],
+                    "aggfunction": "sum",
+                    "condition": { "column": "channel", "operator": "eq", "value": "web" }
+                },
+                {
+                    "columns": ["price"],
+                    "newcolumns": ["web orders"],
+                    "aggfunction": "count",
+                    "condition": { "column": "channel", "operator": "eq", "value": "web" }
+                }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            r#"group { `city` } ( aggregate { `web sales` = sum (case [`channel` == "web" => `price`]), `web orders` = sum (case [`channel` == "web" => 1, true => 0]) } )"#
+        );
+    }
+
+    #[test]
+    fn conditional_unsupported_function() {
+        let input = json!({
+            "aggregations": [
+                {
+                    "columns": ["price"],
+                    "newcolumns": ["web median"],
+                    "aggfunction": "median",
+                    "condition": { "column": "channel", "operator": "eq", "value": "web" }
+                }
+            ]
+        });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::null_grouped(
+        "null",
+        false,
+        r#"group { `city` } ( aggregate { `total` = s"SUM({`price`})" } )"#
+    )]
+    #[case::null_windowed(
+        "null",
+        true,
+        "group { `city` } ( window rows:.. ( derive { `total` = sum `price` } ) )"
+    )]
+    #[case::zero_grouped(
+        "zero",
+        false,
+        "group { `city` } ( aggregate { `total` = sum `price` } )"
+    )]
+    #[case::zero_windowed(
+        "zero",
+        true,
+        "group { `city` } ( window rows:.. ( derive { `total` = (sum `price`) ?? 0 } ) )"
+    )]
+    fn empty_sum(
+        #[case] policy: &str,
+        #[case] keep_original_granularity: bool,
+        #[case] prql: &str,
+    ) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["total"], "aggfunction": "sum" }
+            ],
+            "keepOriginalGranularity": keep_original_granularity,
+            "emptySum": policy
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&Dialect::Postgres)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn null_empty_conditional_sum() {
+        let input = json!({
+            "aggregations": [
+                {
+                    "columns": ["price"],
+                    "newcolumns": ["web sales"],
+                    "aggfunction": "sum",
+                    "condition": { "column": "channel", "operator": "eq", "value": "web" }
+                }
+            ],
+            "emptySum": "null"
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&Dialect::Postgres)
+                .unwrap(),
+            r#"derive { `__web sales_values` = (case [`channel` == "web" => `price`]) } | aggregate { `web sales` = s"SUM({`__web sales_values`})" }"#
+        );
+    }
+
+    #[rstest]
+    #[case::mismatched_columns(
+        json!([
+            { "columns": ["price"], "newcolumns": ["total"], "aggfunction": "sum" },
+            { "columns": ["price", "quantity"], "newcolumns": ["average price"], "aggfunction": "avg" }
+        ]),
+        InvalidAggregation::MismatchedColumns { aggregation: 1, columns: 2, new_columns: 1 }
+    )]
+    #[case::duplicate_new_column(
+        json!([
+            { "columns": ["price"], "newcolumns": ["price"], "aggfunction": "sum" },
+            { "columns": ["quantity"], "newcolumns": ["total"], "aggfunction": "sum" },
+            { "columns": ["price"], "newcolumns": ["price"], "aggfunction": "avg" }
+        ]),
+        InvalidAggregation::DuplicateNewColumn { aggregation: 2, column: Column("price".to_string()) }
+    )]
+    #[case::shadowed_group_column(
+        json!([
+            { "columns": ["city"], "newcolumns": ["city"], "aggfunction": "min" }
+        ]),
+        InvalidAggregation::ShadowedGroupColumn { aggregation: 0, column: Column("city".to_string()) }
+    )]
+    fn invalid_aggregations(
+        #[case] aggregations: serde_json::Value,
+        #[case] expected: InvalidAggregation,
+    ) {
+        let input = json!({ "on": ["city"], "aggregations": aggregations });
+        let error = serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .validate(None, &Dialect::Postgres)
+            .unwrap_err();
+        assert_eq!(error.downcast_ref::<InvalidAggregation>(), Some(&expected));
+    }
+
+    #[rstest]
+    #[case::keep_granularity(true, false)]
+    #[case::aggregated_rows(false, true)]
+    fn new_column_of_input(#[case] keep_original_granularity: bool, #[case] valid: bool) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["price"], "aggfunction": "median" }
+            ],
+            "keepOriginalGranularity": keep_original_granularity
+        });
+        let columns = ["city", "price"].map(|name| Column(name.to_string()));
+        let result = serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .validate(Some(&columns), &Dialect::Postgres);
+        assert_eq!(result.is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case::distinct_groups(
+        json!({ "on": ["city", "country"], "aggregations": [] }),
+        "group { `city`, `country` } ( take 1 ) | select { `city`, `country` }"
+    )]
+    #[case::keep_granularity(
+        json!({ "on": ["city"], "aggregations": [], "keepOriginalGranularity": true }),
+        "derive {}"
+    )]
+    fn without_aggregations(#[case] input: serde_json::Value, #[case] prql: &str) {
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&Dialect::Postgres)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn without_aggregations_nor_groups() {
+        let input = json!({ "aggregations": [] });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::trailing_rows(
+        json!({ "frame": "rows", "start": -2, "end": 0, "orderBy": "day" }),
+        r#"group { `city` } ( sort { `day` } | window rows:-2..0 ( derive { `total` = (sum `price`) ?? 0, `variance` = s"VAR_SAMP({`price`}) OVER (PARTITION BY {`city`} ORDER BY {`day`} ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)" } ) )"#
+    )]
+    #[case::range_to_current_row(
+        json!({ "frame": "range", "end": 0, "orderBy": "day" }),
+        r#"group { `city` } ( sort { `day` } | window range:..0 ( derive { `total` = (sum `price`) ?? 0, `variance` = s"VAR_SAMP({`price`}) OVER (PARTITION BY {`city`} ORDER BY {`day`} RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW)" } ) )"#
+    )]
+    #[case::range_offsets(
+        json!({ "frame": "range", "start": -7, "end": 7, "orderBy": "day" }),
+        r#"group { `city` } ( sort { `day` } | window range:-7..7 ( derive { `total` = (sum `price`) ?? 0, `variance` = s"VAR_SAMP({`price`}) OVER (PARTITION BY {`city`} ORDER BY {`day`} RANGE BETWEEN 7 PRECEDING AND 7 FOLLOWING)" } ) )"#
+    )]
+    fn window_frame(#[case] window: serde_json::Value, #[case] prql: &str) {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["total"], "aggfunction": "sum" },
+                { "columns": ["price"], "newcolumns": ["variance"], "aggfunction": "variance" }
+            ],
+            "keepOriginalGranularity": true,
+            "window": window
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        for dialect in [Dialect::Postgres, Dialect::BigQuery] {
+            assert!(step.validate(None, &dialect).is_ok());
+            assert_eq!(step.to_prql(&dialect).unwrap(), prql);
+        }
+    }
+
+    #[test]
+    fn window_frame_without_groups() {
+        let input = json!({
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["running total"], "aggfunction": "sum" }
+            ],
+            "keepOriginalGranularity": true,
+            "window": { "end": 0, "orderBy": "day" }
+        });
+        assert_eq!(
+            serde_json::from_value::<AggregateStep>(input)
+                .unwrap()
+                .to_prql(&Dialect::BigQuery)
+                .unwrap(),
+            "group {} ( sort { `day` } | window rows:..0 ( derive { `running total` = (sum `price`) ?? 0 } ) )"
+        );
+    }
+
+    #[rstest]
+    #[case::not_keeping_granularity(false, json!({ "orderBy": "day" }))]
+    #[case::start_after_end(true, json!({ "start": 1, "end": -1, "orderBy": "day" }))]
+    fn invalid_window_frame(
+        #[case] keep_original_granularity: bool,
+        #[case] window: serde_json::Value,
+    ) {
+        let input = json!({
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["total"], "aggfunction": "sum" }
+            ],
+            "keepOriginalGranularity": keep_original_granularity,
+            "window": window
+        });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .validate(None, &Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn unframeable_aggregation(#[case] dialect: Dialect) {
+        let input = json!({
+            "aggregations": [
+                { "columns": ["price"], "newcolumns": ["total"], "aggfunction": "sum" },
+                { "columns": ["price"], "newcolumns": ["median"], "aggfunction": "median" }
+            ],
+            "keepOriginalGranularity": true,
+            "window": { "end": 0, "orderBy": "day" }
+        });
+        let error = serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .validate(None, &dialect)
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<InvalidAggregation>(),
+            Some(&InvalidAggregation::Unframeable { aggregation: 1 })
+        );
+    }
+
+    #[test]
+    fn window_frame_without_aggregations() {
+        let input = json!({
+            "on": ["city"],
+            "aggregations": [],
+            "keepOriginalGranularity": true,
+            "window": { "end": 0, "orderBy": "day" }
+        });
+        assert!(serde_json::from_value::<AggregateStep>(input)
+            .unwrap()
+            .validate(None, &Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::last(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "last", "orderBy": "day" }), false, false)]
+    #[case::median(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "median" }), false, false)]
+    #[case::stddev(json!({ "columns": ["price"], "newcolumns": ["p"], "aggfunction": "stddev" }), true, true)]
+    fn frameable_aggregations(
+        #[case] aggregation: serde_json::Value,
+        #[case] postgres: bool,
+        #[case] bigquery: bool,
+    ) {
+        let input = json!({
+            "aggregations": [aggregation],
+            "keepOriginalGranularity": true,
+            "window": { "start": -1, "end": 1, "orderBy": "day" }
+        });
+        let step = serde_json::from_value::<AggregateStep>(input).unwrap();
+        let render = |dialect: &Dialect| match step.needs_join(dialect) {
+            true => step.to_prql_relation("step_1", dialect),
+            false => step.to_prql(dialect),
+        };
+        assert_eq!(render(&Dialect::Postgres).is_ok(), postgres);
+        assert_eq!(render(&Dialect::BigQuery).is_ok(), bigquery);
+    }
 }
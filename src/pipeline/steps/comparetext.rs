@@ -0,0 +1,77 @@
+use crate::pipeline::steps::with_derived_columns;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToSString};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompareTextStep {
+    #[serde(rename = "newColumnName")]
+    new_column_name: Column,
+    #[serde(rename = "strCol1")]
+    str_col_1: Column,
+    #[serde(rename = "strCol2")]
+    str_col_2: Column,
+}
+
+impl ToPrql for CompareTextStep {
+    // Texts are compared byte by byte, whatever the collation of the columns
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let comparison = match dialect {
+            Dialect::Postgres => format!(
+                r#"s"{} COLLATE \"C\" = {} COLLATE \"C\"""#,
+                self.str_col_1.to_s_string(dialect)?,
+                self.str_col_2.to_s_string(dialect)?
+            ),
+            // Strings without an explicit collation are compared as binary in BigQuery
+            Dialect::BigQuery => format!(
+                "{} == {}",
+                self.str_col_1.to_prql(dialect)?,
+                self.str_col_2.to_prql(dialect)?
+            ),
+        };
+        Ok(format!(
+            "derive {{ {} = {} }}",
+            self.new_column_name.to_prql(dialect)?,
+            comparison
+        ))
+    }
+}
+
+impl Schema for CompareTextStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        with_derived_columns(input, [&self.new_column_name])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"derive { `same name` = s"\"First name\" COLLATE \"C\" = \"Nickname\" COLLATE \"C\"" }"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        "derive { `same name` = `First name` == `Nickname` }"
+    )]
+    fn comparetext_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "newColumnName": "same name",
+            "strCol1": "First name",
+            "strCol2": "Nickname"
+        });
+        assert_eq!(
+            serde_json::from_value::<CompareTextStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlReplacing};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Casts columns to a data type. The safe casts of Postgres rely on
+/// `pg_input_is_valid`, hence need Postgres 16 or later.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConvertStep {
+    columns: Vec<Column>,
+    data_type: DataType,
+    // Values which cannot be converted become null instead of failing the query
+    #[serde(default)]
+    safe: bool,
+}
+
+impl ToPrqlReplacing for ConvertStep {
+    // Casts are not supported in PRQL, we hence rely on s-strings
+    // (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn replacements(&self, dialect: &Dialect) -> Result<Vec<(Column, String)>> {
+        if self.columns.is_empty() {
+            bail!("The convert step needs at least one column")
+        }
+        self.columns
+            .iter()
+            .map(|col| Ok((col.clone(), self.cast(col, dialect)?)))
+            .collect()
+    }
+}
+
+impl Schema for ConvertStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        input
+    }
+}
+
+impl ConvertStep {
+    fn cast(&self, column: &Column, dialect: &Dialect) -> Result<String> {
+        let column = format!("{{{}}}", column.to_prql(dialect)?);
+        let type_name = self.data_type.type_name(dialect);
+        let text = format!("CAST({} AS TEXT)", column);
+        match (self.safe, dialect, &self.data_type) {
+            (false, _, _) | (true, Dialect::Postgres, DataType::Text) => {
+                Ok(format!("CAST({} AS {})", column, type_name))
+            }
+            (true, Dialect::BigQuery, _) => Ok(format!("SAFE_CAST({} AS {})", column, type_name)),
+            // Postgres has no safe cast, so values are cast from their text representation
+            // once checked by pg_input_is_valid, which does not fail. As with SAFE_CAST,
+            // numbers are rounded to integers but texts of non-integral numbers are not
+            // converted.
+            (true, Dialect::Postgres, DataType::Integer) => {
+                let rounded = format!("ROUND(CAST({} AS NUMERIC))", text);
+                Ok(format!(
+                    "CASE WHEN pg_input_is_valid({}, '{}') THEN CAST({} AS {}) WHEN pg_typeof({}) IN ('text'::regtype, 'character varying'::regtype, 'character'::regtype) OR NOT pg_input_is_valid({}, 'NUMERIC') THEN NULL WHEN pg_input_is_valid(CAST({} AS TEXT), '{}') THEN CAST({} AS {}) END",
+                    text, type_name, text, type_name, column, text, rounded, type_name, rounded, type_name
+                ))
+            }
+            (true, Dialect::Postgres, _) => Ok(format!(
+                "CASE WHEN pg_input_is_valid({}, '{}') THEN CAST({} AS {}) END",
+                text, type_name, text, type_name
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum DataType {
+    Integer,
+    Float,
+    Text,
+    Date,
+    Boolean,
+}
+
+impl DataType {
+    fn type_name(&self, dialect: &Dialect) -> &'static str {
+        match (self, dialect) {
+            (DataType::Integer, Dialect::Postgres) => "INT8",
+            (DataType::Integer, Dialect::BigQuery) => "INT64",
+            (DataType::Float, Dialect::Postgres) => "DOUBLE PRECISION",
+            (DataType::Float, Dialect::BigQuery) => "FLOAT64",
+            (DataType::Text, Dialect::Postgres) => "TEXT",
+            (DataType::Text, Dialect::BigQuery) => "STRING",
+            (DataType::Date, _) => "DATE",
+            (DataType::Boolean, Dialect::Postgres) => "BOOLEAN",
+            (DataType::Boolean, Dialect::BigQuery) => "BOOL",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn input() -> Vec<Column> {
+        ["Code", "Price", "Unit cost", "Quantity"]
+            .map(|col| Column(col.to_string()))
+            .to_vec()
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"select { `Code`, `Price` = s"CAST({`Price`} AS DOUBLE PRECISION)", `Unit cost` = s"CAST({`Unit cost`} AS DOUBLE PRECISION)", `Quantity` }"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"select { `Code`, `Price` = s"CAST({`Price`} AS FLOAT64)", `Unit cost` = s"CAST({`Unit cost`} AS FLOAT64)", `Quantity` }"#
+    )]
+    fn convert_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input_json = json!({
+            "columns": ["Price", "Unit cost"],
+            "data_type": "float"
+        });
+        assert_eq!(
+            serde_json::from_value::<ConvertStep>(input_json)
+                .unwrap()
+                .to_prql_replacing(Some(&input()), &dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[rstest]
+    #[case::postgres_integer(
+        Dialect::Postgres,
+        "integer",
+        r#"select { `Code`, `Price`, `Unit cost`, `Quantity` = s"CASE WHEN pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'INT8') THEN CAST(CAST({`Quantity`} AS TEXT) AS INT8) WHEN pg_typeof({`Quantity`}) IN ('text'::regtype, 'character varying'::regtype, 'character'::regtype) OR NOT pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'NUMERIC') THEN NULL WHEN pg_input_is_valid(CAST(ROUND(CAST(CAST({`Quantity`} AS TEXT) AS NUMERIC)) AS TEXT), 'INT8') THEN CAST(ROUND(CAST(CAST({`Quantity`} AS TEXT) AS NUMERIC)) AS INT8) END" }"#
+    )]
+    #[case::postgres_date(
+        Dialect::Postgres,
+        "date",
+        r#"select { `Code`, `Price`, `Unit cost`, `Quantity` = s"CASE WHEN pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'DATE') THEN CAST(CAST({`Quantity`} AS TEXT) AS DATE) END" }"#
+    )]
+    #[case::postgres_text(
+        Dialect::Postgres,
+        "text",
+        r#"select { `Code`, `Price`, `Unit cost`, `Quantity` = s"CAST({`Quantity`} AS TEXT)" }"#
+    )]
+    #[case::bigquery_integer(
+        Dialect::BigQuery,
+        "integer",
+        r#"select { `Code`, `Price`, `Unit cost`, `Quantity` = s"SAFE_CAST({`Quantity`} AS INT64)" }"#
+    )]
+    fn convert_safe(#[case] dialect: Dialect, #[case] data_type: &str, #[case] prql: &str) {
+        let input_json = json!({
+            "columns": ["Quantity"],
+            "data_type": data_type,
+            "safe": true
+        });
+        assert_eq!(
+            serde_json::from_value::<ConvertStep>(input_json)
+                .unwrap()
+                .to_prql_replacing(Some(&input()), &dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn convert_safe_fractional_text() {
+        let step = serde_json::from_value::<ConvertStep>(json!({
+            "columns": ["Quantity"],
+            "data_type": "integer",
+            "safe": true
+        }))
+        .unwrap();
+        // Texts such as '1.5' are not valid integers, so they become null as with
+        // SAFE_CAST, while only values of other types are rounded as numbers
+        let cast = step
+            .cast(&Column("Quantity".to_string()), &Dialect::Postgres)
+            .unwrap();
+        assert!(cast.starts_with(
+            "CASE WHEN pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'INT8') THEN CAST(CAST({`Quantity`} AS TEXT) AS INT8) WHEN pg_typeof({`Quantity`}) IN ('text'::regtype, 'character varying'::regtype, 'character'::regtype) OR NOT pg_input_is_valid(CAST({`Quantity`} AS TEXT), 'NUMERIC') THEN NULL "
+        ));
+    }
+
+    #[rstest]
+    #[case::unknown_input(None)]
+    #[case::missing_column(Some(vec![Column("Code".to_string())]))]
+    fn convert_unknown_columns(#[case] columns: Option<Vec<Column>>) {
+        let input_json = json!({
+            "columns": ["Quantity"],
+            "data_type": "integer"
+        });
+        assert!(serde_json::from_value::<ConvertStep>(input_json)
+            .unwrap()
+            .to_prql_replacing(columns.as_deref(), &Dialect::Postgres)
+            .is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::translate::{to_sql, Column, Dialect, Schema, ToPrql};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// Replaced by the result of the previous steps in the query, as in weaverbird
+const PREVIOUS_STEP: &str = "##PREVIOUS_STEP##";
+
+// Statements and clauses which could modify the database, its permissions or settings.
+// Rejecting them is a best-effort safeguard against mistakes, not a security boundary:
+// queries must be run by a read-only role or in a read-only transaction.
+const FORBIDDEN_KEYWORDS: [&str; 20] = [
+    "ALTER", "CALL", "COPY", "CREATE", "DELETE", "DO", "DROP", "EXECUTE", "EXPORT", "GRANT",
+    "INSERT", "INTO", "LOAD", "LOCK", "MERGE", "RENAME", "REVOKE", "SET", "TRUNCATE", "UPDATE",
+];
+
+// Postgres functions with side effects on the server, its sessions or sequences
+const FORBIDDEN_FUNCTIONS: [&str; 17] = [
+    "dblink",
+    "dblink_exec",
+    "lo_export",
+    "lo_import",
+    "nextval",
+    "pg_advisory_lock",
+    "pg_advisory_xact_lock",
+    "pg_cancel_backend",
+    "pg_read_binary_file",
+    "pg_read_file",
+    "pg_reload_conf",
+    "pg_rotate_logfile",
+    "pg_switch_wal",
+    "pg_terminate_backend",
+    "set_config",
+    "setval",
+    "pg_promote",
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CustomSqlStep {
+    query: String,
+}
+
+impl ToPrql for CustomSqlStep {
+    // The query is used as a relation through an s-string
+    // (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        if self.query.contains(PREVIOUS_STEP) {
+            bail!(
+                "The query of a first customsql step cannot refer to {}",
+                PREVIOUS_STEP
+            )
+        }
+        self.validate_query(dialect)?;
+        Ok(format!(r#"from s"{}""#, escape(self.statement())))
+    }
+}
+
+impl Schema for CustomSqlStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        None
+    }
+}
+
+impl CustomSqlStep {
+    /// Renders the query with the previous steps, given as PRQL, compiled to a
+    /// subquery aliased as `previous_step` in place of `##PREVIOUS_STEP##`
+    pub fn to_prql_wrapping(&self, previous: &str, dialect: &Dialect) -> Result<String> {
+        if !self.query.contains(PREVIOUS_STEP) {
+            bail!(
+                "The query of a customsql step following other steps must refer to them with {}",
+                PREVIOUS_STEP
+            )
+        }
+        self.validate_query(dialect)?;
+        let subquery = format!("({}) AS previous_step", to_sql(previous, dialect)?);
+        Ok(format!(
+            r#"from s"{}""#,
+            escape(&self.statement().replace(PREVIOUS_STEP, &subquery))
+        ))
+    }
+
+    // The query without its final semicolon, as it is used as a subquery
+    fn statement(&self) -> &str {
+        self.query.trim().trim_end_matches(';')
+    }
+
+    // Only single statements reading data are accepted
+    fn validate_query(&self, dialect: &Dialect) -> Result<()> {
+        let words = unquoted_words(self.statement(), dialect)?;
+        match words.first().map(|word| word.to_uppercase()).as_deref() {
+            Some("SELECT") | Some("WITH") => {}
+            _ => bail!("The query of a customsql step must start with SELECT or WITH"),
+        }
+        for word in words {
+            if FORBIDDEN_KEYWORDS.contains(&word.to_uppercase().as_str()) {
+                bail!("{} is not allowed in customsql steps", word)
+            }
+            if FORBIDDEN_FUNCTIONS.contains(&word.to_lowercase().as_str()) {
+                bail!("The {} function is not allowed in customsql steps", word)
+            }
+        }
+        Ok(())
+    }
+}
+
+// Braces are doubled and quotes escaped so that the query is kept as is in the s-string
+pub(crate) fn escape(query: &str) -> String {
+    query
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('{', "{{")
+        .replace('}', "}}")
+}
+
+// Words of the query outside of literals, quoted identifiers and comments
+fn unquoted_words<'a>(query: &'a str, dialect: &Dialect) -> Result<Vec<&'a str>> {
+    let mut words = vec![];
+    let mut start: Option<usize> = None;
+    let mut chars = query.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(index);
+            continue;
+        }
+        let prefix = start.take().map(|start| &query[start..index]);
+        // Backslashes escape quotes in BigQuery literals and identifiers, except raw
+        // ones, and in Postgres escape string constants (E'...')
+        let escapes = match (dialect, prefix.map(str::to_uppercase).as_deref()) {
+            (Dialect::BigQuery, Some("R" | "RB" | "BR")) => false,
+            (Dialect::BigQuery, _) => true,
+            (Dialect::Postgres, Some("E")) => c == '\'',
+            (Dialect::Postgres, _) => false,
+        };
+        words.extend(prefix);
+        match c {
+            '\'' | '"' | '`' if !skip_quoted(&mut chars, c, escapes) => {
+                bail!("Unterminated quote in the query of a customsql step")
+            }
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                chars.by_ref().find(|(_, next)| *next == '\n');
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut previous = ' ';
+                let closed = chars.by_ref().any(|(_, next)| {
+                    let end = previous == '*' && next == '/';
+                    previous = next;
+                    end
+                });
+                if !closed {
+                    bail!("Unterminated comment in the query of a customsql step")
+                }
+            }
+            ';' => bail!("The query of a customsql step must be a single statement"),
+            _ => {}
+        }
+    }
+    if let Some(start) = start {
+        words.push(&query[start..]);
+    }
+    Ok(words)
+}
+
+// Skips the rest of a quoted literal or identifier, returning whether it was closed
+fn skip_quoted(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    quote: char,
+    escapes: bool,
+) -> bool {
+    while let Some((_, next)) = chars.next() {
+        match next {
+            '\\' if escapes => {
+                chars.next();
+            }
+            _ if next == quote => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn customsql_first_step(#[case] dialect: Dialect) {
+        let input = json!({
+            "query": r#"SELECT "Country", COUNT(*) AS "count" FROM "al bums" WHERE "Label" = 'a{b}' GROUP BY 1;"#
+        });
+        assert_eq!(
+            serde_json::from_value::<CustomSqlStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            r#"from s"SELECT \"Country\", COUNT(*) AS \"count\" FROM \"al bums\" WHERE \"Label\" = 'a{{b}}' GROUP BY 1""#
+        );
+    }
+
+    #[rstest]
+    #[case::delete("DELETE FROM albums")]
+    #[case::cte_delete("WITH deleted AS (DELETE FROM albums RETURNING *) SELECT * FROM deleted")]
+    #[case::several_statements("SELECT 1; DROP TABLE albums")]
+    #[case::previous_step("SELECT * FROM ##PREVIOUS_STEP##")]
+    #[case::unterminated_quote("SELECT 'a")]
+    fn customsql_invalid(#[case] query: &str) {
+        let step = CustomSqlStep {
+            query: query.to_string(),
+        };
+        assert!(step.to_prql(&Dialect::Postgres).is_err());
+    }
+
+    #[rstest]
+    #[case::select_into("SELECT * INTO albums_copy FROM albums")]
+    #[case::terminate_backend("SELECT pg_terminate_backend(42)")]
+    #[case::set_config("SELECT SET_CONFIG('search_path', 'public', false)")]
+    fn customsql_side_effects(#[case] query: &str) {
+        let step = CustomSqlStep {
+            query: query.to_string(),
+        };
+        assert!(step.to_prql(&Dialect::Postgres).is_err());
+    }
+
+    #[rstest]
+    #[case::bigquery_escaped_quote(Dialect::BigQuery, r"SELECT 'it\'s' AS `a\`b`", true)]
+    #[case::bigquery_raw_string(Dialect::BigQuery, r"SELECT r'\' AS a", true)]
+    #[case::postgres_backslash(Dialect::Postgres, r"SELECT 'C:\' AS a", true)]
+    #[case::postgres_escape_string(Dialect::Postgres, r"SELECT E'it\'s' AS a", true)]
+    #[case::postgres_unterminated(Dialect::Postgres, r"SELECT 'it\'s' AS a", false)]
+    fn customsql_escapes(#[case] dialect: Dialect, #[case] query: &str, #[case] valid: bool) {
+        let step = CustomSqlStep {
+            query: query.to_string(),
+        };
+        assert_eq!(step.to_prql(&dialect).is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case::literal("SELECT 'DROP TABLE albums' AS \"delete\" -- update\n FROM albums")]
+    #[case::comment("SELECT /* insert; */ * FROM albums")]
+    fn customsql_quoted_keywords(#[case] query: &str) {
+        let step = CustomSqlStep {
+            query: query.to_string(),
+        };
+        assert!(step.to_prql(&Dialect::Postgres).is_ok());
+    }
+}
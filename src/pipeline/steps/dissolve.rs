@@ -0,0 +1,136 @@
+use crate::pipeline::steps::aggregate::{aggregations_are_exact, Aggregation};
+use crate::pipeline::steps::GEOMETRY_COLUMN;
+use crate::translate::{Column, Dialect, Schema, ToPrql};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DissolveStep {
+    groups: Vec<Column>,
+    // Whether rows with null values in the groups columns are dissolved together
+    #[serde(default)]
+    include_nulls: bool,
+    #[serde(default)]
+    aggregations: Vec<Aggregation>,
+}
+
+impl DissolveStep {
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        aggregations_are_exact(&self.aggregations, dialect)
+    }
+}
+
+impl ToPrql for DissolveStep {
+    // The geometries of each group are merged, other columns being aggregated as usual
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        if !dialect.supports_spatial() {
+            bail!("The dissolve step is not supported by this dialect")
+        }
+        if self.groups.is_empty() {
+            bail!("The dissolve step needs at least one group column")
+        }
+        let geometry = Column(GEOMETRY_COLUMN.to_string());
+        let union = match dialect {
+            Dialect::Postgres => "ST_Union",
+            Dialect::BigQuery => "ST_UNION_AGG",
+        };
+        let aggregations = [Ok(format!(
+            r#"{} = s"{}({{{}}})""#,
+            geometry.to_prql(dialect)?,
+            union,
+            geometry.to_prql(dialect)?
+        ))]
+        .into_iter()
+        .chain(self.aggregations.iter().map(|agg| agg.to_prql(dialect)))
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+        let groups = self
+            .groups
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?;
+        let group = format!(
+            "group {{ {} }} ( aggregate {{ {} }} )",
+            groups.join(", "),
+            aggregations
+        );
+        match self.include_nulls {
+            true => Ok(group),
+            false => Ok(format!(
+                "filter ({}) | {}",
+                groups
+                    .iter()
+                    .map(|col| format!("{} != null", col))
+                    .collect::<Vec<String>>()
+                    .join(" && "),
+                group
+            )),
+        }
+    }
+}
+
+impl Schema for DissolveStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        Some(
+            self.groups
+                .iter()
+                .cloned()
+                .chain([Column(GEOMETRY_COLUMN.to_string())])
+                .chain(
+                    self.aggregations
+                        .iter()
+                        .flat_map(|agg| agg.new_columns.iter().cloned()),
+                )
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"filter (`region` != null) | group { `region` } ( aggregate { `geometry` = s"ST_Union({`geometry`})", `population` = sum `population` } )"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"filter (`region` != null) | group { `region` } ( aggregate { `geometry` = s"ST_UNION_AGG({`geometry`})", `population` = sum `population` } )"#
+    )]
+    fn dissolve_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "groups": ["region"],
+            "aggregations": [
+                { "columns": ["population"], "newcolumns": ["population"], "aggfunction": "sum" }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<DissolveStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn dissolve_include_nulls() {
+        let input = json!({
+            "groups": ["country", "region"],
+            "include_nulls": true
+        });
+        assert_eq!(
+            serde_json::from_value::<DissolveStep>(input)
+                .unwrap()
+                .to_prql(&Dialect::Postgres)
+                .unwrap(),
+            r#"group { `country`, `region` } ( aggregate { `geometry` = s"ST_Union({`geometry`})" } )"#
+        );
+    }
+}
@@ -1,10 +1,12 @@
-use crate::translate::{Column, Dialect, ToPrql};
+use crate::translate::{Column, Dialect, Schema, ToPrql};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DomainStep {
     domain: Column,
+    // Columns of the domain, when known
+    columns: Option<Vec<Column>>,
 }
 
 impl ToPrql for DomainStep {
@@ -14,6 +16,12 @@ impl ToPrql for DomainStep {
     }
 }
 
+impl Schema for DomainStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        self.columns.clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -0,0 +1,87 @@
+use crate::pipeline::steps::with_derived_columns;
+use crate::translate::{Column, Dialect, Schema, ToPrql};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateStep {
+    column: Column,
+    #[serde(rename = "newColumnName")]
+    new_column_name: Column,
+}
+
+impl ToPrql for DuplicateStep {
+    // https://prql-lang.org/book/reference/stdlib/transforms/derive.html
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        Ok(format!(
+            "derive {{ {} = {} }}",
+            self.new_column_name.to_prql(dialect)?,
+            self.column.to_prql(dialect)?
+        ))
+    }
+}
+
+impl Schema for DuplicateStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        with_derived_columns(input, [&self.new_column_name])
+    }
+
+    fn validate(&self, input: Option<&[Column]>, _dialect: &Dialect) -> Result<()> {
+        match input {
+            Some(columns) if columns.contains(&self.new_column_name) => bail!(
+                "Cannot duplicate {} as {}: this column already exists",
+                self.column.0,
+                self.new_column_name.0
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn duplicate_basic(#[case] dialect: Dialect) {
+        let input = json!({
+            "column": "Price",
+            "newColumnName": "Original price"
+        });
+        assert_eq!(
+            serde_json::from_value::<DuplicateStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            "derive { `Original price` = `Price` }"
+        );
+    }
+
+    #[test]
+    fn duplicate_validate() {
+        let step = serde_json::from_value::<DuplicateStep>(json!({
+            "column": "Price",
+            "newColumnName": "Original price"
+        }))
+        .unwrap();
+        assert!(step.validate(None, &Dialect::Postgres).is_ok());
+        assert!(step
+            .validate(Some(&[Column("Price".to_string())]), &Dialect::Postgres)
+            .is_ok());
+        assert!(step
+            .validate(
+                Some(&[
+                    Column("Price".to_string()),
+                    Column("Original price".to_string())
+                ]),
+                &Dialect::Postgres
+            )
+            .is_err());
+    }
+}
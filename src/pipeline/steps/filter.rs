@@ -1,5 +1,6 @@
-use crate::translate::{Column, Dialect, ToPrql, ToSString, Value};
-use anyhow::Result;
+use crate::pipeline::steps::regex::translate_regex;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToSString, Value};
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,12 +15,19 @@ impl ToPrql for FilterStep {
     }
 }
 
+impl Schema for FilterStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        input
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
-enum Condition {
+pub(crate) enum Condition {
     Simple(SimpleCondition),
     Or(OrCondition),
     And(AndCondition),
+    Not(NotCondition),
 }
 
 impl ToPrql for Condition {
@@ -29,18 +37,20 @@ impl ToPrql for Condition {
             Condition::Simple(condition) => condition.to_prql(dialect),
             Condition::Or(condition) => condition.to_prql(dialect),
             Condition::And(condition) => condition.to_prql(dialect),
+            Condition::Not(condition) => condition.to_prql(dialect),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
-enum SimpleCondition {
+pub(crate) enum SimpleCondition {
     Comparison(ComparisonCondition),
     Nullability(NullabilityCondition),
     Inclusion(InclusionCondition),
     Matches(MatchesCondition),
-    // Date(DateCondition),
+    Date(DateCondition),
+    Text(TextCondition),
 }
 
 impl ToPrql for SimpleCondition {
@@ -51,37 +61,99 @@ impl ToPrql for SimpleCondition {
             SimpleCondition::Nullability(condition) => condition.to_prql(dialect),
             SimpleCondition::Inclusion(condition) => condition.to_prql(dialect),
             SimpleCondition::Matches(condition) => condition.to_prql(dialect),
-            // SimpleCondition::Date(condition) => condition.to_prql(dialect),
+            SimpleCondition::Date(condition) => condition.to_prql(dialect),
+            SimpleCondition::Text(condition) => condition.to_prql(dialect),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct ComparisonCondition {
+pub(crate) struct ComparisonCondition {
     column: Column,
     operator: ComparisonOperator,
-    value: Value,
+    value: ComparisonValue,
+    // Only used by the `between` operator, whose bounds are inclusive by default
+    bounds: Option<Bounds>,
 }
 
 impl ToPrql for ComparisonCondition {
     fn to_prql(&self, dialect: &Dialect) -> Result<String> {
-        let op = match self.operator {
-            ComparisonOperator::Eq => "==",
-            ComparisonOperator::Ne => "!=",
-            ComparisonOperator::Gt => ">",
-            ComparisonOperator::Gte => ">=",
-            ComparisonOperator::Lt => "<",
-            ComparisonOperator::Lte => "<=",
+        let column = self.column.to_prql(dialect)?;
+        let op = match (&self.operator, &self.value, &self.bounds) {
+            (ComparisonOperator::Eq, ComparisonValue::Single(_), None) => "==",
+            (ComparisonOperator::Ne, ComparisonValue::Single(_), None) => "!=",
+            (ComparisonOperator::Gt, ComparisonValue::Single(_), None) => ">",
+            (ComparisonOperator::Gte, ComparisonValue::Single(_), None) => ">=",
+            (ComparisonOperator::Lt, ComparisonValue::Single(_), None) => "<",
+            (ComparisonOperator::Lte, ComparisonValue::Single(_), None) => "<=",
+            // Ranges are compiled to BETWEEN, which includes its bounds
+            // (https://prql-lang.org/book/reference/syntax/ranges.html)
+            (
+                ComparisonOperator::Between,
+                ComparisonValue::Range([low, high]),
+                None | Some(Bounds::Inclusive),
+            ) => {
+                return Ok(format!(
+                    "({} | in {}..{})",
+                    column,
+                    low.to_prql(dialect)?,
+                    high.to_prql(dialect)?
+                ))
+            }
+            (
+                ComparisonOperator::Between,
+                ComparisonValue::Range([low, high]),
+                Some(Bounds::Exclusive),
+            ) => {
+                return Ok(format!(
+                    "({} > {} && {} < {})",
+                    column,
+                    low.to_prql(dialect)?,
+                    column,
+                    high.to_prql(dialect)?
+                ))
+            }
+            (ComparisonOperator::Between, _, _) => {
+                bail!("The between operator needs a lower and an upper bound")
+            }
+            (_, ComparisonValue::Range(_), _) => {
+                bail!("Only the between operator can be used with two values")
+            }
+            (_, _, Some(_)) => bail!("Bounds can only be given to the between operator"),
         };
         Ok(format!(
             "{} {} {}",
-            self.column.to_prql(dialect)?,
+            column,
             op,
-            self.value
+            self.value.to_prql(dialect)?
         ))
     }
 }
 
+// Untagged, two values being tried as a range before being tried as an array literal
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum ComparisonValue {
+    Range([Operand; 2]),
+    Single(Operand),
+}
+
+impl ToPrql for ComparisonValue {
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        match self {
+            ComparisonValue::Range(_) => bail!("A range cannot be used as a single value"),
+            ComparisonValue::Single(operand) => operand.to_prql(dialect),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Bounds {
+    Inclusive,
+    Exclusive,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 enum ComparisonOperator {
@@ -91,10 +163,49 @@ enum ComparisonOperator {
     Gte,
     Lt,
     Lte,
+    Between,
 }
 
+// A reference to another column, e.g. {"column": "Cost"}
 #[derive(Serialize, Deserialize, Debug)]
-struct NullabilityCondition {
+struct ColumnReference {
+    column: Column,
+}
+
+// Right-hand side of conditions, being either another column or a literal
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum Operand {
+    Column(ColumnReference),
+    Value(Value),
+}
+
+impl ToPrql for Operand {
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        match self {
+            Operand::Column(reference) => reference.column.to_prql(dialect),
+            Operand::Value(Value::Array(_) | Value::Object(_)) => {
+                bail!("Only scalar values and column references can be compared")
+            }
+            Operand::Value(value) => Ok(value.to_string()),
+        }
+    }
+}
+
+impl ToSString for Operand {
+    fn to_s_string(&self, dialect: &Dialect) -> Result<String> {
+        match self {
+            Operand::Column(reference) => reference.column.to_s_string(dialect),
+            Operand::Value(Value::Array(_) | Value::Object(_)) => {
+                bail!("Only scalar values and column references can be compared")
+            }
+            Operand::Value(value) => value.to_s_string(dialect),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct NullabilityCondition {
     column: Column,
     operator: NullabilityOperator,
 }
@@ -118,10 +229,10 @@ enum NullabilityOperator {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct InclusionCondition {
+pub(crate) struct InclusionCondition {
     column: Column,
     operator: InclusionOperator,
-    value: Vec<Value>,
+    value: Vec<Operand>,
 }
 
 impl ToPrql for InclusionCondition {
@@ -157,35 +268,66 @@ enum InclusionOperator {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct MatchesCondition {
+pub(crate) struct MatchesCondition {
     column: Column,
     operator: MatchesOperator,
-    value: String,
+    value: Pattern,
+    #[serde(default)]
+    case_insensitive: bool,
 }
 
 impl ToPrql for MatchesCondition {
     fn to_prql(&self, dialect: &Dialect) -> Result<String> {
-        match (&self.operator, dialect) {
-            (MatchesOperator::Matches, Dialect::Postgres) => Ok(format!(
-                r#"s"{} SIMILAR TO {}""#,
-                self.column.to_s_string(dialect)?,
-                self.value.to_s_string(dialect)?,
-            )),
-            (MatchesOperator::NotMatches, Dialect::Postgres) => Ok(format!(
-                r#"s"{} NOT SIMILAR TO {}""#,
-                self.column.to_s_string(dialect)?,
-                self.value.to_s_string(dialect)?,
-            )),
-            (MatchesOperator::Matches, Dialect::BigQuery) => Ok(format!(
-                r#"s"REGEXP_CONTAINS({},{})""#,
-                self.column.to_s_string(dialect)?,
-                self.value.to_s_string(dialect)?,
-            )),
-            (MatchesOperator::NotMatches, Dialect::BigQuery) => Ok(format!(
-                r#"s"NOT REGEXP_CONTAINS({},{})""#,
-                self.column.to_s_string(dialect)?,
-                self.value.to_s_string(dialect)?,
-            )),
+        let column = self.column.to_s_string(dialect)?;
+        // Case insensitivity is a flag of the pattern itself with RE2
+        let pattern = match (&self.value, dialect, self.case_insensitive) {
+            (Pattern::Column(reference), Dialect::BigQuery, true) => {
+                format!("CONCAT('(?i)', {})", reference.column.to_s_string(dialect)?)
+            }
+            (Pattern::Column(reference), _, _) => reference.column.to_s_string(dialect)?,
+            (Pattern::Text(text), Dialect::BigQuery, true) => {
+                translate_regex(&format!("(?i){}", text), dialect)?.to_s_string(dialect)?
+            }
+            (Pattern::Text(text), _, _) => translate_regex(text, dialect)?.to_s_string(dialect)?,
+        };
+        match dialect {
+            // https://www.postgresql.org/docs/current/functions-matching.html#FUNCTIONS-POSIX-REGEXP
+            Dialect::Postgres => {
+                let op = match (&self.operator, self.case_insensitive) {
+                    (MatchesOperator::Matches, false) => "~",
+                    (MatchesOperator::Matches, true) => "~*",
+                    (MatchesOperator::NotMatches, false) => "!~",
+                    (MatchesOperator::NotMatches, true) => "!~*",
+                };
+                Ok(format!(r#"s"{} {} {}""#, column, op, pattern))
+            }
+            Dialect::BigQuery => {
+                let negation = match self.operator {
+                    MatchesOperator::Matches => "",
+                    MatchesOperator::NotMatches => "NOT ",
+                };
+                Ok(format!(
+                    r#"s"{}REGEXP_CONTAINS({},{})""#,
+                    negation, column, pattern
+                ))
+            }
+        }
+    }
+}
+
+// Patterns are either given as text or read from another column
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum Pattern {
+    Column(ColumnReference),
+    Text(String),
+}
+
+impl ToSString for Pattern {
+    fn to_s_string(&self, dialect: &Dialect) -> Result<String> {
+        match self {
+            Pattern::Column(reference) => reference.column.to_s_string(dialect),
+            Pattern::Text(text) => text.to_s_string(dialect),
         }
     }
 }
@@ -198,7 +340,236 @@ enum MatchesOperator {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct OrCondition {
+pub(crate) struct TextCondition {
+    column: Column,
+    operator: TextOperator,
+    value: String,
+    #[serde(default)]
+    case_insensitive: bool,
+}
+
+impl ToPrql for TextCondition {
+    // Text functions are not part of the standard library of PRQL 0.10, we hence
+    // rely on s-strings (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let negation = match self.operator {
+            TextOperator::StartsWith | TextOperator::EndsWith | TextOperator::Contains => "",
+            TextOperator::NotStartsWith | TextOperator::NotEndsWith | TextOperator::NotContains => {
+                "NOT "
+            }
+        };
+        let column = self.column.to_s_string(dialect)?;
+        match dialect {
+            // `!` is used as escape character, backslashes having a meaning in s-strings
+            Dialect::Postgres => {
+                let escaped = self
+                    .value
+                    .replace('!', "!!")
+                    .replace('%', "!%")
+                    .replace('_', "!_");
+                let pattern = match self.operator {
+                    TextOperator::StartsWith | TextOperator::NotStartsWith => {
+                        format!("{}%", escaped)
+                    }
+                    TextOperator::EndsWith | TextOperator::NotEndsWith => format!("%{}", escaped),
+                    TextOperator::Contains | TextOperator::NotContains => {
+                        format!("%{}%", escaped)
+                    }
+                };
+                let like = match self.case_insensitive {
+                    true => "ILIKE",
+                    false => "LIKE",
+                };
+                Ok(format!(
+                    r#"s"{} {}{} {} ESCAPE '!'""#,
+                    column,
+                    negation,
+                    like,
+                    pattern.to_s_string(dialect)?
+                ))
+            }
+            // BigQuery has no ILIKE, so dedicated functions are used instead of LIKE
+            Dialect::BigQuery => {
+                let (column, value) = match self.case_insensitive {
+                    true => (
+                        format!("LOWER({})", column),
+                        format!("LOWER({})", self.value.to_s_string(dialect)?),
+                    ),
+                    false => (column, self.value.to_s_string(dialect)?),
+                };
+                let test = match self.operator {
+                    TextOperator::StartsWith | TextOperator::NotStartsWith => {
+                        format!("STARTS_WITH({}, {})", column, value)
+                    }
+                    TextOperator::EndsWith | TextOperator::NotEndsWith => {
+                        format!("ENDS_WITH({}, {})", column, value)
+                    }
+                    TextOperator::Contains | TextOperator::NotContains => {
+                        format!("STRPOS({}, {}) > 0", column, value)
+                    }
+                };
+                Ok(format!(r#"s"{}{}""#, negation, test))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum TextOperator {
+    StartsWith,
+    EndsWith,
+    Contains,
+    NotStartsWith,
+    NotEndsWith,
+    NotContains,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct DateCondition {
+    column: Column,
+    operator: DateOperator,
+    value: DateBound,
+}
+
+impl ToPrql for DateCondition {
+    // Both bounds are inclusive
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let op = match self.operator {
+            DateOperator::From => ">=",
+            DateOperator::Until => "<=",
+        };
+        Ok(format!(
+            "{} {} {}",
+            self.column.to_prql(dialect)?,
+            op,
+            self.value.to_prql(dialect)?
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum DateOperator {
+    From,
+    Until,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum DateBound {
+    Relative(RelativeDate),
+    // ISO 8601 date or date and time, e.g. 2023-01-31 or 2023-01-31T12:00:00Z
+    Absolute(String),
+}
+
+impl ToPrql for DateBound {
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        match self {
+            DateBound::Relative(date) => date.to_prql(dialect),
+            // https://prql-lang.org/book/reference/syntax/literals.html#date-and-time
+            DateBound::Absolute(date) if is_iso_date(date) || is_iso_datetime(date) => {
+                Ok(format!("@{}", date))
+            }
+            DateBound::Absolute(date) => bail!("Invalid date {}: expected an ISO 8601 date", date),
+        }
+    }
+}
+
+// A date relative to today or to an ISO date, e.g. a week ago is
+// {"date": "today", "quantity": -7, "duration": "day"}
+#[derive(Serialize, Deserialize, Debug)]
+struct RelativeDate {
+    date: String,
+    quantity: i64,
+    duration: Duration,
+}
+
+impl ToPrql for RelativeDate {
+    // Date arithmetic differs between dialects, we hence rely on s-strings
+    // (https://prql-lang.org/book/reference/syntax/s-strings.html)
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let date = match self.date.as_str() {
+            "today" => "CURRENT_DATE".to_string(),
+            date if is_iso_date(date) => format!("DATE {}", date.to_string().to_s_string(dialect)?),
+            date => bail!(
+                "Invalid relative date {}: expected today or an ISO 8601 date",
+                date
+            ),
+        };
+        match dialect {
+            // Adding an interval to a date gives a timestamp in Postgres
+            Dialect::Postgres => {
+                let (quantity, unit) = match self.duration {
+                    Duration::Day => (self.quantity, "days"),
+                    Duration::Week => (self.quantity, "weeks"),
+                    Duration::Month => (self.quantity, "months"),
+                    Duration::Quarter => match self.quantity.checked_mul(3) {
+                        Some(months) => (months, "months"),
+                        None => bail!(
+                            "Invalid relative date: {} quarters is too large",
+                            self.quantity
+                        ),
+                    },
+                    Duration::Year => (self.quantity, "years"),
+                };
+                Ok(format!(
+                    r#"s"CAST({} + INTERVAL '{} {}' AS DATE)""#,
+                    date, quantity, unit
+                ))
+            }
+            Dialect::BigQuery => {
+                let unit = match self.duration {
+                    Duration::Day => "DAY",
+                    Duration::Week => "WEEK",
+                    Duration::Month => "MONTH",
+                    Duration::Quarter => "QUARTER",
+                    Duration::Year => "YEAR",
+                };
+                Ok(format!(
+                    r#"s"DATE_ADD({}, INTERVAL {} {})""#,
+                    date, self.quantity, unit
+                ))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Duration {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+// Checks a string against a pattern where `9` stands for any digit
+fn matches_digits_pattern(value: &str, pattern: &str) -> bool {
+    value.len() == pattern.len()
+        && value.chars().zip(pattern.chars()).all(|(c, p)| match p {
+            '9' => c.is_ascii_digit(),
+            _ => c == p,
+        })
+}
+
+fn is_iso_date(value: &str) -> bool {
+    matches_digits_pattern(value, "9999-99-99")
+}
+
+// Seconds may have a fractional part and the time be followed by a UTC `Z`
+fn is_iso_datetime(value: &str) -> bool {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (datetime, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    matches_digits_pattern(datetime, "9999-99-99T99:99:99")
+        && !fraction.is_empty()
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OrCondition {
     or: Vec<Condition>,
 }
 
@@ -216,7 +587,8 @@ impl ToPrql for OrCondition {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct AndCondition {
+#[serde(deny_unknown_fields)]
+pub(crate) struct AndCondition {
     and: Vec<Condition>,
 }
 
@@ -231,6 +603,18 @@ impl ToPrql for AndCondition {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NotCondition {
+    not: Box<Condition>,
+}
+
+impl ToPrql for NotCondition {
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        Ok(format!("!({})", self.not.to_prql(dialect)?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -381,7 +765,7 @@ mod tests {
     #[rstest]
     #[case::postgres(
         Dialect::Postgres,
-        r#"filter s"\"val1\" SIMILAR TO 'pika'" && s"\"val2\" NOT SIMILAR TO 'chu'""#
+        r#"filter s"\"val1\" ~ '(?p)pika'" && s"\"val2\" !~ '(?p)chu'""#
     )]
     #[case::bigquery(
         Dialect::BigQuery,
@@ -413,4 +797,219 @@ mod tests {
             prql
         );
     }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"filter `order date` >= @2023-01-01 && `order date` <= s"CAST(CURRENT_DATE + INTERVAL '-7 days' AS DATE)" && `time` <= @2023-06-30T12:00:00.5Z && `order date` >= s"CAST(DATE '2023-01-01' + INTERVAL '6 months' AS DATE)""#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"filter `order date` >= @2023-01-01 && `order date` <= s"DATE_ADD(CURRENT_DATE, INTERVAL -7 DAY)" && `time` <= @2023-06-30T12:00:00.5Z && `order date` >= s"DATE_ADD(DATE '2023-01-01', INTERVAL 2 QUARTER)""#
+    )]
+    fn filter_date(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!(
+        {
+            "condition": {
+                "and": [
+                    {
+                        "column": "order date",
+                        "operator": "from",
+                        "value": "2023-01-01",
+                    },
+                    {
+                        "column": "order date",
+                        "operator": "until",
+                        "value": { "date": "today", "quantity": -7, "duration": "day" },
+                    },
+                    {
+                        "column": "time",
+                        "operator": "until",
+                        "value": "2023-06-30T12:00:00.5Z",
+                    },
+                    {
+                        "column": "order date",
+                        "operator": "from",
+                        "value": { "date": "2023-01-01", "quantity": 2, "duration": "quarter" },
+                    },
+                ]
+            }
+        });
+        assert_eq!(
+            serde_json::from_value::<FilterStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[rstest]
+    #[case::not_a_date(json!("yesterday"))]
+    #[case::not_iso(json!("01/31/2023"))]
+    #[case::relative_not_iso(json!({ "date": "2023-1-1", "quantity": 1, "duration": "year" }))]
+    #[case::too_many_quarters(json!({ "date": "today", "quantity": i64::MAX / 2, "duration": "quarter" }))]
+    fn filter_invalid_date(#[case] value: serde_json::Value) {
+        let input = json!(
+        {
+            "condition": {
+                "column": "date",
+                "operator": "from",
+                "value": value,
+            }
+        });
+        assert!(serde_json::from_value::<FilterStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"filter `Price` > `Cost` && s"\"Label\" IN ('none', \"Brand\")" && s"\"Label\" ~ \"Label pattern\"""#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"filter `Price` > `Cost` && s"`Label` IN ('none', `Brand`)" && s"REGEXP_CONTAINS(`Label`,`Label pattern`)""#
+    )]
+    fn filter_column_references(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!(
+        {
+            "condition": {
+                "and": [
+                    {
+                        "column": "Price",
+                        "operator": "gt",
+                        "value": { "column": "Cost" },
+                    },
+                    {
+                        "column": "Label",
+                        "operator": "in",
+                        "value": ["none", { "column": "Brand" }],
+                    },
+                    {
+                        "column": "Label",
+                        "operator": "matches",
+                        "value": { "column": "Label pattern" },
+                    },
+                ]
+            }
+        });
+        assert_eq!(
+            serde_json::from_value::<FilterStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"filter s"\"Name\" LIKE 'Dr.%' ESCAPE '!'" && s"\"Name\" NOT ILIKE '%jr' ESCAPE '!'" && s"\"Code\" LIKE '%1!%!_!!%' ESCAPE '!'""#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"filter s"STARTS_WITH(`Name`, 'Dr.')" && s"NOT ENDS_WITH(LOWER(`Name`), LOWER('jr'))" && s"STRPOS(`Code`, '1%_!') > 0""#
+    )]
+    fn filter_text(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!(
+        {
+            "condition": {
+                "and": [
+                    {
+                        "column": "Name",
+                        "operator": "startswith",
+                        "value": "Dr.",
+                    },
+                    {
+                        "column": "Name",
+                        "operator": "notendswith",
+                        "value": "jr",
+                        "case_insensitive": true,
+                    },
+                    {
+                        "column": "Code",
+                        "operator": "contains",
+                        "value": "1%_!",
+                    },
+                ]
+            }
+        });
+        assert_eq!(
+            serde_json::from_value::<FilterStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn filter_not_between(#[case] dialect: Dialect) {
+        let input = json!(
+        {
+            "condition": {
+                "and": [
+                    {
+                        "column": "Price",
+                        "operator": "between",
+                        "value": [10, { "column": "Max price" }],
+                    },
+                    {
+                        "not": {
+                            "column": "Quantity",
+                            "operator": "between",
+                            "value": [0, 5],
+                            "bounds": "exclusive",
+                        }
+                    },
+                    {
+                        "not": {
+                            "or": [
+                                { "column": "City", "operator": "eq", "value": "Paris" },
+                                { "column": "City", "operator": "isnull" },
+                            ]
+                        }
+                    },
+                ]
+            }
+        });
+        assert_eq!(
+            serde_json::from_value::<FilterStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            r#"filter (`Price` | in 10..`Max price`) && !((`Quantity` > 0 && `Quantity` < 5)) && !((`City` == "Paris" || `City` == null))"#
+        );
+    }
+
+    #[rstest]
+    #[case::between_single_value(json!({ "column": "Price", "operator": "between", "value": 10 }))]
+    #[case::between_three_values(json!({ "column": "Price", "operator": "between", "value": [1, 2, 3] }))]
+    #[case::range_without_between(json!({ "column": "Price", "operator": "eq", "value": [1, 2] }))]
+    #[case::bounds_without_between(json!({ "column": "Price", "operator": "gt", "value": 1, "bounds": "exclusive" }))]
+    fn filter_invalid_between(#[case] condition: serde_json::Value) {
+        let input = json!({ "condition": condition });
+        assert!(serde_json::from_value::<FilterStep>(input)
+            .unwrap()
+            .to_prql(&Dialect::Postgres)
+            .is_err());
+    }
+
+    #[test]
+    fn filter_ambiguous_combinator() {
+        let input = json!(
+        {
+            "condition": {
+                "not": { "column": "City", "operator": "isnull" },
+                "or": [],
+            }
+        });
+        assert!(serde_json::from_value::<FilterStep>(input).is_err());
+    }
 }
@@ -0,0 +1,200 @@
+use crate::pipeline::steps::filter::Condition;
+use crate::pipeline::steps::with_derived_columns;
+use crate::translate::{Column, Dialect, Schema, ToPrql, Value};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IfThenElseStep {
+    #[serde(rename = "newColumn")]
+    new_column: Column,
+    #[serde(flatten)]
+    branch: IfThenElse,
+}
+
+impl ToPrql for IfThenElseStep {
+    // https://prql-lang.org/book/reference/syntax/case.html
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        Ok(format!(
+            "derive {{ {} = case [ {} ] }}",
+            self.new_column.to_prql(dialect)?,
+            self.branch.to_prql(dialect)?
+        ))
+    }
+}
+
+impl Schema for IfThenElseStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        with_derived_columns(input, [&self.new_column])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IfThenElse {
+    #[serde(rename = "if")]
+    condition: Condition,
+    then: Expression,
+    #[serde(rename = "else")]
+    otherwise: ElseBranch,
+}
+
+impl ToPrql for IfThenElse {
+    // Nested if/then/else are flattened into the arms of a single `case`
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let arm = format!(
+            "{} => {}",
+            self.condition.to_prql(dialect)?,
+            self.then.to_prql(dialect)?
+        );
+        match &self.otherwise {
+            ElseBranch::Nested(nested) => Ok(format!("{}, {}", arm, nested.to_prql(dialect)?)),
+            ElseBranch::Expression(expression) => {
+                Ok(format!("{}, true => {}", arm, expression.to_prql(dialect)?))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum ElseBranch {
+    Nested(Box<IfThenElse>),
+    Expression(Expression),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum Expression {
+    Formula { formula: String },
+    Literal(Value),
+}
+
+impl ToPrql for Expression {
+    fn to_prql(&self, _dialect: &Dialect) -> Result<String> {
+        match self {
+            Expression::Formula { formula } => Ok(formula_to_prql(formula)),
+            Expression::Literal(Value::Array(_) | Value::Object(_)) => {
+                bail!("Only scalar values can be used as literals")
+            }
+            Expression::Literal(value) => Ok(value.to_string()),
+        }
+    }
+}
+
+// Weaverbird formulas reference columns as `[my column]`, which is written
+// `` `my column` `` in PRQL, brackets within string literals being kept
+fn formula_to_prql(formula: &str) -> String {
+    let mut prql = String::with_capacity(formula.len());
+    let mut quote: Option<char> = None;
+    let mut chars = formula.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '[' | ']') => prql.push('`'),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                prql.push(c);
+            }
+            (Some(_), '\\') => {
+                prql.push(c);
+                prql.extend(chars.next());
+            }
+            (Some(q), _) if q == c => {
+                quote = None;
+                prql.push(c);
+            }
+            _ => prql.push(c),
+        }
+    }
+    prql
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn ifthenelse_basic(#[case] dialect: Dialect) {
+        let input = json!({
+            "newColumn": "is Paris",
+            "if": {
+                "column": "City",
+                "value": "Paris",
+                "operator": "eq"
+            },
+            "then": true,
+            "else": false
+        });
+        assert_eq!(
+            serde_json::from_value::<IfThenElseStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            r#"derive { `is Paris` = case [ `City` == "Paris" => true, true => false ] }"#
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn ifthenelse_nested(#[case] dialect: Dialect) {
+        let input = json!({
+            "newColumn": "discounted price",
+            "if": {
+                "column": "Quantity",
+                "value": 100,
+                "operator": "gte"
+            },
+            "then": { "formula": "[Unit Price] * 0.8" },
+            "else": {
+                "if": {
+                    "or": [
+                        { "column": "Client", "operator": "eq", "value": "VIP" },
+                        { "column": "Quantity", "operator": "gte", "value": 10 }
+                    ]
+                },
+                "then": { "formula": "[Unit Price] * 0.9" },
+                "else": { "formula": "[Unit Price]" }
+            }
+        });
+        assert_eq!(
+            serde_json::from_value::<IfThenElseStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            r#"derive { `discounted price` = case [ `Quantity` >= 100 => `Unit Price` * 0.8, (`Client` == "VIP" || `Quantity` >= 10) => `Unit Price` * 0.9, true => `Unit Price` ] }"#
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn ifthenelse_rejects_non_scalar_literal(#[case] dialect: Dialect) {
+        let input = json!({
+            "newColumn": "oops",
+            "if": { "column": "City", "operator": "isnull" },
+            "then": [1, 2],
+            "else": null
+        });
+        assert!(serde_json::from_value::<IfThenElseStep>(input)
+            .unwrap()
+            .to_prql(&dialect)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::column("[Unit Price] * 2", "`Unit Price` * 2")]
+    #[case::string_literal(r#"[Code] + "a[1]""#, r#"`Code` + "a[1]""#)]
+    #[case::escaped_quote(
+        r#"'it\'s [not] a column' + [Name]"#,
+        r#"'it\'s [not] a column' + `Name`"#
+    )]
+    fn formula_brackets(#[case] formula: &str, #[case] prql: &str) {
+        assert_eq!(formula_to_prql(formula), prql);
+    }
+}
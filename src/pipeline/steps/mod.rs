@@ -1,6 +1,142 @@
+use crate::pipeline::steps::customsql::escape;
+use crate::translate::{to_sql, Column, Dialect, ToPrql};
+use anyhow::{bail, Result};
+
+pub(crate) mod absolutevalue;
+pub(crate) use absolutevalue::AbsoluteValueStep;
 pub(crate) mod aggregate;
 pub(crate) use aggregate::AggregateStep;
+pub(crate) mod comparetext;
+pub(crate) use comparetext::CompareTextStep;
+pub(crate) mod convert;
+pub(crate) use convert::ConvertStep;
+pub(crate) mod customsql;
+pub(crate) use customsql::CustomSqlStep;
+pub(crate) mod dissolve;
+pub(crate) use dissolve::DissolveStep;
 pub(crate) mod domain;
 pub(crate) use domain::DomainStep;
+pub(crate) mod duplicate;
+pub(crate) use duplicate::DuplicateStep;
 pub(crate) mod filter;
 pub(crate) use filter::FilterStep;
+pub(crate) mod ifthenelse;
+pub(crate) use ifthenelse::IfThenElseStep;
+pub(crate) mod regex;
+pub(crate) mod rollup;
+pub(crate) use rollup::RollupStep;
+pub(crate) mod simplify;
+pub(crate) use simplify::SimplifyStep;
+pub(crate) mod statistics;
+pub(crate) use statistics::StatisticsStep;
+pub(crate) mod totals;
+pub(crate) use totals::TotalsStep;
+pub(crate) mod uniquegroups;
+pub(crate) use uniquegroups::UniqueGroupsStep;
+pub(crate) mod waterfall;
+pub(crate) use waterfall::WaterfallStep;
+
+// Column holding the geometries in geographic datasets
+const GEOMETRY_COLUMN: &str = "geometry";
+
+/// Adds the derived columns to those of the input, when they are known
+pub(crate) fn with_derived_columns<'a>(
+    input: Option<Vec<Column>>,
+    derived: impl IntoIterator<Item = &'a Column>,
+) -> Option<Vec<Column>> {
+    let mut columns = input?;
+    for column in derived {
+        if !columns.contains(column) {
+            columns.push(column.clone());
+        }
+    }
+    Some(columns)
+}
+
+/// Selects the columns of the input, the replaced ones being computed in place by
+/// the given SQL expressions
+pub(crate) fn with_replaced_columns(
+    input: Option<&[Column]>,
+    replaced: &[(Column, String)],
+    dialect: &Dialect,
+) -> Result<String> {
+    let Some(input) = input else {
+        bail!("The columns of the input must be known to replace some of them with this dialect: list them in the columns of the domain step, and avoid steps whose output columns are unknown such as customsql")
+    };
+    if let Some((column, _)) = replaced.iter().find(|(column, _)| !input.contains(column)) {
+        bail!("Column {} is not a column of the input", column.0)
+    }
+    Ok(format!(
+        "select {{ {} }}",
+        input
+            .iter()
+            .map(|column| {
+                let name = column.to_prql(dialect)?;
+                Ok(
+                    match replaced.iter().find(|(replaced, _)| replaced == column) {
+                        Some((_, expression)) => format!(r#"{} = s"{}""#, name, expression),
+                        None => name,
+                    },
+                )
+            })
+            .collect::<Result<Vec<String>>>()?
+            .join(", ")
+    ))
+}
+
+/// Replaces columns of the result of the previous steps, given as PRQL, without
+/// knowing its other columns, with `SELECT * REPLACE` which only BigQuery supports
+pub(crate) fn with_all_columns_replaced(
+    previous: &str,
+    replaced: &[(Column, String)],
+    dialect: &Dialect,
+) -> Result<String> {
+    if !matches!(dialect, Dialect::BigQuery) {
+        return with_replaced_columns(None, replaced, dialect);
+    }
+    let replacements = replaced
+        .iter()
+        .map(|(column, expression)| {
+            Ok(format!(
+                "{} AS {{{}}}",
+                expression,
+                column.to_prql(dialect)?
+            ))
+        })
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+    Ok(format!(
+        r#"from s"SELECT * REPLACE ({}) FROM ({}) AS previous_step""#,
+        replacements,
+        escape(&to_sql(previous, dialect)?)
+    ))
+}
+
+/// Keeps the distinct combinations of values of the columns, in their given order,
+/// compiled to SELECT DISTINCT
+/// (https://prql-lang.org/book/reference/stdlib/transforms/group.html#distinct)
+pub(crate) fn distinct(on: &[Column], dialect: &Dialect) -> Result<String> {
+    let on = on
+        .iter()
+        .map(|col| col.to_prql(dialect))
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+    Ok(format!(
+        "group {{ {} }} ( take 1 ) | select {{ {} }}",
+        on, on
+    ))
+}
+
+/// Appends relations one after the other
+/// (https://prql-lang.org/book/reference/stdlib/transforms/append.html)
+pub(crate) fn append(relations: &[String]) -> String {
+    relations
+        .iter()
+        .enumerate()
+        .map(|(index, relation)| match index {
+            0 => relation.clone(),
+            _ => format!("append ({})", relation),
+        })
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
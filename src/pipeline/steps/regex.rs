@@ -0,0 +1,309 @@
+use crate::translate::Dialect;
+use anyhow::{bail, Result};
+
+/// Flavours of regular expressions of the engines used by each dialect
+enum RegexFlavour {
+    // Advanced regular expressions (https://www.postgresql.org/docs/current/functions-matching.html#POSIX-SYNTAX-DETAILS)
+    Postgres,
+    // https://github.com/google/re2/wiki/Syntax
+    Re2,
+}
+
+impl From<&Dialect> for RegexFlavour {
+    fn from(dialect: &Dialect) -> Self {
+        match dialect {
+            Dialect::Postgres => RegexFlavour::Postgres,
+            Dialect::BigQuery => RegexFlavour::Re2,
+        }
+    }
+}
+
+/// Translates a regular expression to the flavour of the dialect, failing on
+/// constructs its engine does not support
+pub(crate) fn translate_regex(pattern: &str, dialect: &Dialect) -> Result<String> {
+    let flavour = RegexFlavour::from(dialect);
+    let mut translated = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    let mut in_class = false;
+    let mut quantified = false;
+    while let Some(c) = chars.next() {
+        let after_quantifier = std::mem::take(&mut quantified);
+        match c {
+            '\\' => {
+                let Some(escaped) = chars.next() else {
+                    bail!("Invalid regular expression {}: trailing backslash", pattern)
+                };
+                match (&flavour, escaped) {
+                    (RegexFlavour::Re2, '1'..='9') if !in_class => bail!(
+                        "Invalid regular expression {}: backreferences are not supported",
+                        pattern
+                    ),
+                    (RegexFlavour::Postgres, 'Q') if !in_class => bail!(
+                        "Invalid regular expression {}: quoted literals are not supported",
+                        pattern
+                    ),
+                    // End of text assertions are spelled differently
+                    (RegexFlavour::Postgres, 'z') if !in_class => translated.push_str(r"\Z"),
+                    (RegexFlavour::Re2, 'Z') if !in_class => translated.push_str(r"\z"),
+                    // `\b` is a backspace in Postgres, word boundaries being `\y`
+                    (RegexFlavour::Postgres, 'b') if !in_class => translated.push_str(r"\y"),
+                    (RegexFlavour::Postgres, 'B') if !in_class => translated.push_str(r"\Y"),
+                    _ => {
+                        translated.push(c);
+                        translated.push(escaped);
+                    }
+                }
+            }
+            '[' if !in_class => {
+                in_class = true;
+                translated.push(c);
+                // A closing bracket right after the opening one is part of the class
+                for first in ['^', ']'] {
+                    if chars.next_if_eq(&first).is_some() {
+                        translated.push(first);
+                    }
+                }
+            }
+            // Character classes such as `[:alpha:]` may appear within brackets
+            '[' if in_class && chars.next_if_eq(&':').is_some() => {
+                translated.push_str("[:");
+                loop {
+                    match chars.next() {
+                        Some(':') if chars.next_if_eq(&']').is_some() => break,
+                        Some(c) if c.is_ascii_alphabetic() => translated.push(c),
+                        _ => bail!(
+                            "Invalid regular expression {}: invalid character class",
+                            pattern
+                        ),
+                    }
+                }
+                translated.push_str(":]");
+            }
+            ']' if in_class => {
+                in_class = false;
+                translated.push(c);
+            }
+            // Neither engine supports possessive quantifiers
+            '+' if !in_class && after_quantifier => bail!(
+                "Invalid regular expression {}: possessive quantifiers are not supported",
+                pattern
+            ),
+            '*' | '+' | '?' if !in_class => {
+                quantified = true;
+                translated.push(c);
+            }
+            '{' if !in_class && is_bound(chars.clone()) => {
+                translated.push(c);
+                translated.extend(chars.by_ref().take_while(|c| *c != '}'));
+                translated.push('}');
+                quantified = true;
+            }
+            '(' if !in_class && chars.next_if_eq(&'?').is_some() => {
+                translated.push_str(&translate_group(
+                    pattern,
+                    &flavour,
+                    translated.is_empty(),
+                    &mut chars,
+                )?);
+            }
+            _ => translated.push(c),
+        }
+    }
+    if in_class {
+        bail!("Invalid regular expression {}: unclosed bracket", pattern)
+    }
+    match flavour {
+        RegexFlavour::Postgres => Ok(with_newline_options(&translated)),
+        RegexFlavour::Re2 => Ok(translated),
+    }
+}
+
+// Whether a bound such as `{2}` or `{2,5}` follows an opening brace
+fn is_bound(mut chars: std::iter::Peekable<std::str::Chars>) -> bool {
+    let mut digits = false;
+    let mut comma = false;
+    for c in chars.by_ref() {
+        match c {
+            '0'..='9' => digits = true,
+            ',' if digits && !comma => comma = true,
+            '}' => return digits,
+            _ => return false,
+        }
+    }
+    false
+}
+
+// RE2 dots do not match newlines and `^` and `$` only match at the start and end
+// of the text, unless the `s` and `m` flags are set. Postgres matches newlines
+// with its default options, the equivalent embedded options are hence set,
+// along with the translated flags at the start of the expression.
+fn with_newline_options(translated: &str) -> String {
+    let (flags, rest) = match translated
+        .strip_prefix("(?")
+        .and_then(|rest| rest.split_once(')'))
+    {
+        Some((flags, rest)) if flags.chars().all(|c| "ims".contains(c)) => (flags, rest),
+        _ => ("", translated),
+    };
+    let newlines = match (flags.contains('s'), flags.contains('m')) {
+        (false, false) => "p",
+        (false, true) => "n",
+        (true, false) => "",
+        (true, true) => "w",
+    };
+    let options = format!("{}{}", flags.replace(['s', 'm'], ""), newlines);
+    match options.is_empty() {
+        true => rest.to_string(),
+        false => format!("(?{}){}", options, rest),
+    }
+}
+
+// Translates the opening of a group starting with `(?`
+fn translate_group(
+    pattern: &str,
+    flavour: &RegexFlavour,
+    at_start: bool,
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<String> {
+    let lookbehind = chars.next_if_eq(&'<').is_some();
+    match (flavour, chars.peek()) {
+        (_, Some(':')) if !lookbehind => {
+            chars.next();
+            Ok("(?:".to_string())
+        }
+        (RegexFlavour::Re2, Some('=' | '!')) => bail!(
+            "Invalid regular expression {}: lookarounds are not supported",
+            pattern
+        ),
+        (RegexFlavour::Postgres, Some(&c @ ('=' | '!'))) => {
+            chars.next();
+            Ok(format!("(?{}{}", if lookbehind { "<" } else { "" }, c))
+        }
+        (_, Some('>')) if !lookbehind => bail!(
+            "Invalid regular expression {}: atomic groups are not supported",
+            pattern
+        ),
+        // Names of groups have no use when matching, which is the only
+        // purpose of the regular expressions here
+        (_, Some('P')) if !lookbehind => {
+            chars.next();
+            if chars.next_if_eq(&'<').is_none() {
+                bail!("Invalid regular expression {}: invalid group", pattern)
+            }
+            skip_group_name(pattern, chars)
+        }
+        (_, Some(_)) if lookbehind => skip_group_name(pattern, chars),
+        // Flags
+        (_, Some(c)) if c.is_ascii_alphabetic() || *c == '-' => {
+            let mut flags = String::new();
+            while let Some(flag) = chars.next_if(|c| c.is_ascii_alphabetic() || *c == '-') {
+                flags.push(flag);
+            }
+            let flags = translate_flags(pattern, flavour, &flags)?;
+            match chars.next() {
+                // Postgres only accepts flags applied to the whole expression
+                Some(')') if flags.is_empty() => Ok(String::new()),
+                Some(')') if at_start || matches!(flavour, RegexFlavour::Re2) => {
+                    Ok(format!("(?{})", flags))
+                }
+                Some(')') => bail!(
+                    "Invalid regular expression {}: flags are only supported at its start",
+                    pattern
+                ),
+                Some(':') if matches!(flavour, RegexFlavour::Re2) => Ok(format!("(?{}:", flags)),
+                _ => bail!(
+                    "Invalid regular expression {}: flags are not supported in groups",
+                    pattern
+                ),
+            }
+        }
+        _ => bail!("Invalid regular expression {}: invalid group", pattern),
+    }
+}
+
+// Flags of RE2 (https://github.com/google/re2/wiki/Syntax#flags), with their
+// equivalent embedded options in Postgres
+fn translate_flags(pattern: &str, flavour: &RegexFlavour, flags: &str) -> Result<String> {
+    match flavour {
+        RegexFlavour::Re2 if flags.chars().all(|c| "imsU-".contains(c)) => Ok(flags.to_string()),
+        // Postgres options cannot be cleared, `s` and `m` are translated to its
+        // newline options once the whole expression is translated
+        RegexFlavour::Postgres if flags.chars().all(|c| "ims".contains(c)) => Ok(flags.to_string()),
+        _ => bail!(
+            "Invalid regular expression {}: unsupported flags {}",
+            pattern,
+            flags
+        ),
+    }
+}
+
+fn skip_group_name(
+    pattern: &str,
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<String> {
+    match chars.by_ref().find(|c| !(c.is_alphanumeric() || *c == '_')) {
+        Some('>') => Ok("(".to_string()),
+        _ => bail!("Invalid regular expression {}: invalid group name", pattern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::plain(r"^pika\d+[a-z]{2}$", r"(?p)^pika\d+[a-z]{2}$", r"^pika\d+[a-z]{2}$")]
+    #[case::named_group(
+        r"(?P<year>\d{4})-(?<month>\d{2})",
+        r"(?p)(\d{4})-(\d{2})",
+        r"(\d{4})-(\d{2})"
+    )]
+    #[case::end_of_text(r"chu\z[\z]", r"(?p)chu\Z[\z]", r"chu\z[\z]")]
+    #[case::flags(r"(?i)pika(?:chu)?", r"(?ip)pika(?:chu)?", r"(?i)pika(?:chu)?")]
+    #[case::bracket(r"[]()?]", r"(?p)[]()?]", r"[]()?]")]
+    #[case::word_boundary(r"\bpika\B", r"(?p)\ypika\Y", r"\bpika\B")]
+    #[case::posix_class(
+        r"[[:alpha:]_][^]:[:digit:]]",
+        r"(?p)[[:alpha:]_][^]:[:digit:]]",
+        r"[[:alpha:]_][^]:[:digit:]]"
+    )]
+    #[case::multiline(r"(?m)^pika$", r"(?n)^pika$", r"(?m)^pika$")]
+    #[case::dot_all(r"(?s)pika.chu", r"pika.chu", r"(?s)pika.chu")]
+    #[case::multiline_dot_all(r"(?ims)^pika.chu$", r"(?iw)^pika.chu$", r"(?ims)^pika.chu$")]
+    #[case::lazy_bound(r"a{2,}?b{,3}+", r"(?p)a{2,}?b{,3}+", r"a{2,}?b{,3}+")]
+    fn translate_valid(#[case] pattern: &str, #[case] postgres: &str, #[case] re2: &str) {
+        assert_eq!(
+            translate_regex(pattern, &Dialect::Postgres).unwrap(),
+            postgres
+        );
+        assert_eq!(translate_regex(pattern, &Dialect::BigQuery).unwrap(), re2);
+    }
+
+    #[rstest]
+    #[case::lookahead(r"pika(?=chu)", true, false)]
+    #[case::lookbehind(r"(?<!pika)chu", true, false)]
+    #[case::backreference(r"(a)\1", true, false)]
+    #[case::inner_flags(r"pika(?i)chu", false, true)]
+    #[case::atomic_group(r"(?>pika)", false, false)]
+    #[case::unclosed_bracket(r"[pika", false, false)]
+    #[case::trailing_backslash(r"pika\", false, false)]
+    #[case::ungreedy(r"(?U)pika.+", false, true)]
+    #[case::cleared_flag(r"(?i)pika(?-i:chu)", false, true)]
+    #[case::unknown_flag(r"(?x)pika", false, false)]
+    #[case::invalid_class(r"[[:alpha]", false, false)]
+    #[case::possessive_star(r"pika.*+", false, false)]
+    #[case::possessive_plus(r"pika++", false, false)]
+    #[case::possessive_optional(r"pika?+", false, false)]
+    #[case::possessive_bound(r"pika{2,3}+", false, false)]
+    #[case::quoted_literal(r"\Qpika.chu\E", false, true)]
+    fn translate_unsupported(#[case] pattern: &str, #[case] postgres: bool, #[case] re2: bool) {
+        assert_eq!(
+            translate_regex(pattern, &Dialect::Postgres).is_ok(),
+            postgres
+        );
+        assert_eq!(translate_regex(pattern, &Dialect::BigQuery).is_ok(), re2);
+    }
+}
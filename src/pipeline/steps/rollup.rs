@@ -0,0 +1,182 @@
+use crate::pipeline::steps::aggregate::{
+    aggregations_are_exact, grouped_aggregation, Aggregation, EmptySum,
+};
+use crate::pipeline::steps::append;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlRelation, Value};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RollupStep {
+    hierarchy: Vec<Column>,
+    aggregations: Vec<Aggregation>,
+    #[serde(default)]
+    groupby: Vec<Column>,
+    #[serde(rename = "labelCol")]
+    #[serde(default = "default_label_col")]
+    label_col: Column,
+    #[serde(rename = "levelCol")]
+    #[serde(default = "default_level_col")]
+    level_col: Column,
+    #[serde(rename = "parentLabelCol")]
+    #[serde(default = "default_parent_label_col")]
+    parent_label_col: Column,
+    #[serde(rename = "emptySum")]
+    empty_sum: Option<EmptySum>,
+}
+
+fn default_label_col() -> Column {
+    Column("label".to_string())
+}
+
+fn default_level_col() -> Column {
+    Column("level".to_string())
+}
+
+fn default_parent_label_col() -> Column {
+    Column("parent".to_string())
+}
+
+impl RollupStep {
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        aggregations_are_exact(&self.aggregations, dialect)
+    }
+}
+
+impl Schema for RollupStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        Some(
+            self.groupby
+                .iter()
+                .chain(&self.hierarchy)
+                .chain([&self.label_col, &self.level_col, &self.parent_label_col])
+                .chain(self.aggregations.iter().flat_map(|agg| &agg.new_columns))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+impl ToPrqlRelation for RollupStep {
+    // Each level of the hierarchy is aggregated separately, with the values of
+    // its deeper levels set to null, and the results are appended
+    fn to_prql_relation(&self, input: &str, dialect: &Dialect) -> Result<String> {
+        if self.hierarchy.is_empty() {
+            bail!("The rollup step needs at least one hierarchy level")
+        }
+        let columns = self
+            .output_columns(None)
+            .unwrap_or_default()
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+        let levels = self
+            .hierarchy
+            .iter()
+            .enumerate()
+            .map(|(index, level)| {
+                let on = self
+                    .groupby
+                    .iter()
+                    .chain(&self.hierarchy[..=index])
+                    .cloned()
+                    .collect::<Vec<Column>>();
+                let parent = match index {
+                    0 => "null".to_string(),
+                    _ => self.hierarchy[index - 1].to_prql(dialect)?,
+                };
+                let derived = self.hierarchy[index + 1..]
+                    .iter()
+                    .map(|deeper| Ok(format!("{} = null", deeper.to_prql(dialect)?)))
+                    .chain([
+                        Ok(format!(
+                            "{} = {}",
+                            self.label_col.to_prql(dialect)?,
+                            level.to_prql(dialect)?
+                        )),
+                        Ok(format!(
+                            "{} = {}",
+                            self.level_col.to_prql(dialect)?,
+                            Value::String(level.0.clone())
+                        )),
+                        Ok(format!(
+                            "{} = {}",
+                            self.parent_label_col.to_prql(dialect)?,
+                            parent
+                        )),
+                    ])
+                    .collect::<Result<Vec<String>>>()?
+                    .join(", ");
+                Ok(format!(
+                    "from {} | {} | derive {{ {} }} | select {{ {} }}",
+                    input,
+                    grouped_aggregation(&on, &self.aggregations, self.empty_sum.as_ref(), dialect)?,
+                    derived,
+                    columns
+                ))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        Ok(append(&levels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn rollup_default_columns(#[case] dialect: Dialect) {
+        let input = json!({
+            "hierarchy": ["CONTINENT", "COUNTRY"],
+            "aggregations": [
+                { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<RollupStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            [
+                r#"from step_1 | group { `CONTINENT` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `COUNTRY` = null, `label` = `CONTINENT`, `level` = "CONTINENT", `parent` = null } | select { `CONTINENT`, `COUNTRY`, `label`, `level`, `parent`, `VALUE` }"#,
+                r#"append (from step_1 | group { `CONTINENT`, `COUNTRY` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `label` = `COUNTRY`, `level` = "COUNTRY", `parent` = `CONTINENT` } | select { `CONTINENT`, `COUNTRY`, `label`, `level`, `parent`, `VALUE` })"#,
+            ]
+            .join(" | ")
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn rollup_with_groupby_and_custom_columns(#[case] dialect: Dialect) {
+        let input = json!({
+            "hierarchy": ["CONTINENT", "COUNTRY", "CITY"],
+            "aggregations": [
+                { "columns": ["VALUE"], "newcolumns": ["TOTAL"], "aggfunction": "sum" }
+            ],
+            "groupby": ["YEAR"],
+            "labelCol": "my label",
+            "levelCol": "my level",
+            "parentLabelCol": "my parent"
+        });
+        assert_eq!(
+            serde_json::from_value::<RollupStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            [
+                r#"from step_1 | group { `YEAR`, `CONTINENT` } ( aggregate { `TOTAL` = sum `VALUE` } ) | derive { `COUNTRY` = null, `CITY` = null, `my label` = `CONTINENT`, `my level` = "CONTINENT", `my parent` = null } | select { `YEAR`, `CONTINENT`, `COUNTRY`, `CITY`, `my label`, `my level`, `my parent`, `TOTAL` }"#,
+                r#"append (from step_1 | group { `YEAR`, `CONTINENT`, `COUNTRY` } ( aggregate { `TOTAL` = sum `VALUE` } ) | derive { `CITY` = null, `my label` = `COUNTRY`, `my level` = "COUNTRY", `my parent` = `CONTINENT` } | select { `YEAR`, `CONTINENT`, `COUNTRY`, `CITY`, `my label`, `my level`, `my parent`, `TOTAL` })"#,
+                r#"append (from step_1 | group { `YEAR`, `CONTINENT`, `COUNTRY`, `CITY` } ( aggregate { `TOTAL` = sum `VALUE` } ) | derive { `my label` = `CITY`, `my level` = "CITY", `my parent` = `COUNTRY` } | select { `YEAR`, `CONTINENT`, `COUNTRY`, `CITY`, `my label`, `my level`, `my parent`, `TOTAL` })"#,
+            ]
+            .join(" | ")
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::pipeline::steps::GEOMETRY_COLUMN;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlReplacing};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Simplifies the geometries of the `geometry` column in place, which needs the
+/// other columns of the input to be known with Postgres
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimplifyStep {
+    // In the units of the coordinates with PostGIS, in meters with BigQuery
+    tolerance: f64,
+}
+
+impl ToPrqlReplacing for SimplifyStep {
+    fn replacements(&self, dialect: &Dialect) -> Result<Vec<(Column, String)>> {
+        if !dialect.supports_spatial() {
+            bail!("The simplify step is not supported by this dialect")
+        }
+        if self.tolerance.is_nan() || self.tolerance < 0.0 {
+            bail!("The tolerance of the simplify step must be positive")
+        }
+        let geometry = Column(GEOMETRY_COLUMN.to_string());
+        let simplified = format!(
+            "ST_Simplify({{{}}}, {})",
+            geometry.to_prql(dialect)?,
+            self.tolerance
+        );
+        Ok(vec![(geometry, simplified)])
+    }
+}
+
+impl Schema for SimplifyStep {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn columns() -> Vec<Column> {
+        ["territory", "geometry", "population"]
+            .map(|name| Column(name.to_string()))
+            .to_vec()
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"select { `territory`, `geometry` = s"ST_Simplify({`geometry`}, 0.5)", `population` }"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"select { `territory`, `geometry` = s"ST_Simplify({`geometry`}, 0.5)", `population` }"#
+    )]
+    fn simplify_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({ "tolerance": 0.5 });
+        assert_eq!(
+            serde_json::from_value::<SimplifyStep>(input)
+                .unwrap()
+                .to_prql_replacing(Some(&columns()), &dialect)
+                .unwrap(),
+            prql
+        );
+    }
+
+    #[test]
+    fn simplify_without_geometry() {
+        let input = json!({ "tolerance": 0.5 });
+        assert!(serde_json::from_value::<SimplifyStep>(input)
+            .unwrap()
+            .to_prql_replacing(Some(&columns()[..1]), &Dialect::Postgres)
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn simplify_negative_tolerance(#[case] dialect: Dialect) {
+        let input = json!({ "tolerance": -1 });
+        assert!(serde_json::from_value::<SimplifyStep>(input)
+            .unwrap()
+            .to_prql_replacing(Some(&columns()), &dialect)
+            .is_err());
+    }
+}
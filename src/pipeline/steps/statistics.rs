@@ -0,0 +1,257 @@
+use crate::pipeline::steps::aggregate::{group_aggregate, sql_function};
+use crate::translate::{Column, Dialect, Schema, ToPrql};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatisticsStep {
+    column: Column,
+    #[serde(rename = "groupbyColumns")]
+    #[serde(default)]
+    groupby_columns: Vec<Column>,
+    #[serde(default)]
+    statistics: Vec<Statistic>,
+    #[serde(default)]
+    quantiles: Vec<Quantile>,
+    // APPROX_QUANTILES is the only quantile aggregate function in BigQuery, exact
+    // quantiles are hence interpolated from the sorted values of each group, which
+    // is slower and needs them to fit in memory
+    #[serde(rename = "exactQuantiles")]
+    #[serde(default)]
+    exact_quantiles: bool,
+}
+
+impl StatisticsStep {
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        match dialect {
+            Dialect::Postgres => true,
+            Dialect::BigQuery => self.quantiles.is_empty() || self.exact_quantiles,
+        }
+    }
+}
+
+impl ToPrql for StatisticsStep {
+    // https://prql-lang.org/book/reference/stdlib/transforms/aggregate.html
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        let aggregations = self
+            .statistics
+            .iter()
+            .map(|statistic| {
+                Ok(format!(
+                    "{} = {}",
+                    statistic.output_column().to_prql(dialect)?,
+                    statistic.to_prql(&self.column, dialect)?
+                ))
+            })
+            .chain(self.quantiles.iter().map(|quantile| {
+                Ok(format!(
+                    "{} = {}",
+                    quantile.output_column().to_prql(dialect)?,
+                    quantile.to_prql(&self.column, self.exact_quantiles, dialect)?
+                ))
+            }))
+            .collect::<Result<Vec<String>>>()?;
+        if aggregations.is_empty() {
+            bail!("The statistics step needs at least one statistic or quantile")
+        }
+        group_aggregate(&self.groupby_columns, &aggregations, dialect)
+    }
+}
+
+impl Schema for StatisticsStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        Some(
+            self.groupby_columns
+                .iter()
+                .cloned()
+                .chain(
+                    self.statistics
+                        .iter()
+                        .map(|statistic| statistic.output_column()),
+                )
+                .chain(
+                    self.quantiles
+                        .iter()
+                        .map(|quantile| quantile.output_column()),
+                )
+                .collect(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Statistic {
+    Count,
+    Max,
+    Min,
+    Average,
+    Variance,
+    #[serde(rename = "standard deviation")]
+    StandardDeviation,
+}
+
+impl Statistic {
+    fn output_column(&self) -> Column {
+        let name = match self {
+            Statistic::Count => "count",
+            Statistic::Max => "max",
+            Statistic::Min => "min",
+            Statistic::Average => "average",
+            Statistic::Variance => "variance",
+            Statistic::StandardDeviation => "standard deviation",
+        };
+        Column(name.to_string())
+    }
+
+    fn to_prql(&self, column: &Column, dialect: &Dialect) -> Result<String> {
+        let column = column.to_prql(dialect)?;
+        Ok(match self {
+            // PRQL's `count` counts rows, null values included
+            Statistic::Count => sql_function("COUNT", &column, ""),
+            Statistic::Max => format!("max {}", column),
+            Statistic::Min => format!("min {}", column),
+            Statistic::Average => format!("average {}", column),
+            Statistic::Variance => sql_function("VAR_SAMP", &column, ""),
+            Statistic::StandardDeviation => sql_function("STDDEV_SAMP", &column, ""),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Quantile {
+    label: Option<String>,
+    nth: u32,
+    order: u32,
+}
+
+impl Quantile {
+    fn output_column(&self) -> Column {
+        match &self.label {
+            Some(label) => Column(label.clone()),
+            None => Column(format!("{}-th {}-quantile", self.nth, self.order)),
+        }
+    }
+
+    fn to_prql(&self, column: &Column, exact: bool, dialect: &Dialect) -> Result<String> {
+        if self.order == 0 || self.nth > self.order {
+            bail!(
+                "Invalid quantile: {} is not between 0 and {}",
+                self.nth,
+                self.order
+            )
+        }
+        let percentile = self.nth as f64 / self.order as f64;
+        let column = column.to_prql(dialect)?;
+        match (dialect, exact) {
+            (Dialect::Postgres, _) => Ok(format!(
+                r#"s"PERCENTILE_CONT({}) WITHIN GROUP (ORDER BY {{{}}})""#,
+                percentile, column
+            )),
+            (Dialect::BigQuery, false) => Ok(format!(
+                r#"s"APPROX_QUANTILES({{{}}}, {})[OFFSET({})]""#,
+                column, self.order, self.nth
+            )),
+            // Interpolated between the closest sorted values as PERCENTILE_CONT, null
+            // values being ignored
+            (Dialect::BigQuery, true) => {
+                let values = format!(
+                    "ARRAY_AGG({{{}}} IGNORE NULLS ORDER BY {{{}}})",
+                    column, column
+                );
+                let position = format!("{} * (COUNT({{{}}}) - 1)", percentile, column);
+                let fraction = format!("({} - FLOOR({}))", position, position);
+                Ok(format!(
+                    r#"s"({}[SAFE_OFFSET(CAST(FLOOR({}) AS INT64))] * (1 - {}) + {}[SAFE_OFFSET(CAST(CEIL({}) AS INT64))] * {})""#,
+                    values, position, fraction, values, position, fraction
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"aggregate { `count` = s"COUNT({`price`})", `max` = max `price`, `min` = min `price`, `average` = average `price` }"#
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"aggregate { `count` = s"COUNT({`price`})", `max` = max `price`, `min` = min `price`, `average` = average `price` }"#
+    )]
+    fn statistics_basic(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "column": "price",
+            "statistics": ["count", "max", "min", "average"]
+        });
+        let step = serde_json::from_value::<StatisticsStep>(input).unwrap();
+        assert_eq!(step.to_prql(&dialect).unwrap(), prql);
+        assert!(step.is_exact(&dialect));
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"group { `city` } ( aggregate { `variance` = s"VAR_SAMP({`price`})", `standard deviation` = s"STDDEV_SAMP({`price`})", `median` = s"PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY {`price`})", `1-th 4-quantile` = s"PERCENTILE_CONT(0.25) WITHIN GROUP (ORDER BY {`price`})" } )"#,
+        true
+    )]
+    #[case::bigquery(
+        Dialect::BigQuery,
+        r#"group { `city` } ( aggregate { `variance` = s"VAR_SAMP({`price`})", `standard deviation` = s"STDDEV_SAMP({`price`})", `median` = s"APPROX_QUANTILES({`price`}, 2)[OFFSET(1)]", `1-th 4-quantile` = s"APPROX_QUANTILES({`price`}, 4)[OFFSET(1)]" } )"#,
+        false
+    )]
+    fn statistics_quantiles(#[case] dialect: Dialect, #[case] prql: &str, #[case] exact: bool) {
+        let input = json!({
+            "column": "price",
+            "groupbyColumns": ["city"],
+            "statistics": ["variance", "standard deviation"],
+            "quantiles": [
+                { "label": "median", "nth": 1, "order": 2 },
+                { "nth": 1, "order": 4 }
+            ]
+        });
+        let step = serde_json::from_value::<StatisticsStep>(input).unwrap();
+        assert_eq!(step.to_prql(&dialect).unwrap(), prql);
+        assert_eq!(step.is_exact(&dialect), exact);
+    }
+
+    #[rstest]
+    #[case::postgres(
+        Dialect::Postgres,
+        r#"group { `city` } ( aggregate { `median` = s"PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY {`price`})" } )"#
+    )]
+    #[case::bigquery(Dialect::BigQuery, r#"group { `city` } ( aggregate { `median` = s"(ARRAY_AGG({`price`} IGNORE NULLS ORDER BY {`price`})[SAFE_OFFSET(CAST(FLOOR(0.5 * (COUNT({`price`}) - 1)) AS INT64))] * (1 - (0.5 * (COUNT({`price`}) - 1) - FLOOR(0.5 * (COUNT({`price`}) - 1)))) + ARRAY_AGG({`price`} IGNORE NULLS ORDER BY {`price`})[SAFE_OFFSET(CAST(CEIL(0.5 * (COUNT({`price`}) - 1)) AS INT64))] * (0.5 * (COUNT({`price`}) - 1) - FLOOR(0.5 * (COUNT({`price`}) - 1))))" } )"#)]
+    fn statistics_exact_quantiles(#[case] dialect: Dialect, #[case] prql: &str) {
+        let input = json!({
+            "column": "price",
+            "groupbyColumns": ["city"],
+            "quantiles": [{ "label": "median", "nth": 1, "order": 2 }],
+            "exactQuantiles": true
+        });
+        let step = serde_json::from_value::<StatisticsStep>(input).unwrap();
+        assert_eq!(step.to_prql(&dialect).unwrap(), prql);
+        assert!(step.is_exact(&dialect));
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn statistics_invalid_quantile(#[case] dialect: Dialect) {
+        let input = json!({
+            "column": "price",
+            "quantiles": [{ "nth": 5, "order": 4 }]
+        });
+        assert!(serde_json::from_value::<StatisticsStep>(input)
+            .unwrap()
+            .to_prql(&dialect)
+            .is_err());
+    }
+}
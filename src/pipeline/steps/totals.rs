@@ -0,0 +1,187 @@
+use crate::pipeline::steps::aggregate::{
+    aggregations_are_exact, grouped_aggregation, Aggregation, EmptySum,
+};
+use crate::pipeline::steps::append;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlRelation, Value};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// Every combination of dimensions is aggregated, so their number must stay small
+const MAX_TOTAL_DIMENSIONS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TotalsStep {
+    #[serde(rename = "totalDimensions")]
+    total_dimensions: Vec<TotalDimension>,
+    aggregations: Vec<Aggregation>,
+    #[serde(default)]
+    groups: Vec<Column>,
+    #[serde(rename = "emptySum")]
+    empty_sum: Option<EmptySum>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TotalDimension {
+    #[serde(rename = "totalColumn")]
+    total_column: Column,
+    #[serde(rename = "totalRowsLabel")]
+    total_rows_label: String,
+}
+
+impl TotalsStep {
+    pub fn is_exact(&self, dialect: &Dialect) -> bool {
+        aggregations_are_exact(&self.aggregations, dialect)
+    }
+}
+
+impl Schema for TotalsStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        Some(
+            self.groups
+                .iter()
+                .chain(self.total_dimensions.iter().map(|dim| &dim.total_column))
+                .chain(self.aggregations.iter().flat_map(|agg| &agg.new_columns))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn validate(&self, _input: Option<&[Column]>, _dialect: &Dialect) -> Result<()> {
+        if self.total_dimensions.len() > MAX_TOTAL_DIMENSIONS {
+            bail!(
+                "The totals step accepts at most {} total dimensions, got {}",
+                MAX_TOTAL_DIMENSIONS,
+                self.total_dimensions.len()
+            )
+        }
+        Ok(())
+    }
+}
+
+impl ToPrqlRelation for TotalsStep {
+    // Every combination of total dimensions is aggregated separately, the totalled
+    // dimensions being replaced by their label, and the results are appended
+    fn to_prql_relation(&self, input: &str, dialect: &Dialect) -> Result<String> {
+        // The number of combinations is only bounded once the step is validated
+        self.validate(None, dialect)?;
+        // `append` is positional, so every part must output its columns in the same order
+        let columns = self
+            .output_columns(None)
+            .unwrap_or_default()
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+        let parts = (0..1 << self.total_dimensions.len())
+            .map(|combination: usize| {
+                let (totalled, kept): (Vec<_>, Vec<_>) = self
+                    .total_dimensions
+                    .iter()
+                    .enumerate()
+                    .partition(|(index, _)| combination & (1 << index) != 0);
+                let on = self
+                    .groups
+                    .iter()
+                    .chain(kept.iter().map(|(_, dim)| &dim.total_column))
+                    .cloned()
+                    .collect::<Vec<Column>>();
+                let mut part = vec![
+                    format!("from {}", input),
+                    grouped_aggregation(&on, &self.aggregations, self.empty_sum.as_ref(), dialect)?,
+                ];
+                if !totalled.is_empty() {
+                    part.push(format!(
+                        "derive {{ {} }}",
+                        totalled
+                            .iter()
+                            .map(|(_, dim)| Ok(format!(
+                                "{} = {}",
+                                dim.total_column.to_prql(dialect)?,
+                                Value::String(dim.total_rows_label.clone())
+                            )))
+                            .collect::<Result<Vec<String>>>()?
+                            .join(", ")
+                    ));
+                }
+                part.push(format!("select {{ {} }}", columns));
+                Ok(part.join(" | "))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        Ok(append(&parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn totals_one_dimension(#[case] dialect: Dialect) {
+        let input = json!({
+            "totalDimensions": [
+                { "totalColumn": "COUNTRY", "totalRowsLabel": "All countries" }
+            ],
+            "aggregations": [
+                { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+            ]
+        });
+        assert_eq!(
+            serde_json::from_value::<TotalsStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            r#"from step_1 | group { `COUNTRY` } ( aggregate { `VALUE` = sum `VALUE` } ) | select { `COUNTRY`, `VALUE` } | append (from step_1 | aggregate { `VALUE` = sum `VALUE` } | derive { `COUNTRY` = "All countries" } | select { `COUNTRY`, `VALUE` })"#
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn totals_two_dimensions_with_groups(#[case] dialect: Dialect) {
+        let input = json!({
+            "totalDimensions": [
+                { "totalColumn": "COUNTRY", "totalRowsLabel": "All countries" },
+                { "totalColumn": "PRODUCT", "totalRowsLabel": "All products" }
+            ],
+            "aggregations": [
+                { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+            ],
+            "groups": ["YEAR"]
+        });
+        assert_eq!(
+            serde_json::from_value::<TotalsStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            [
+                "from step_1 | group { `YEAR`, `COUNTRY`, `PRODUCT` } ( aggregate { `VALUE` = sum `VALUE` } ) | select { `YEAR`, `COUNTRY`, `PRODUCT`, `VALUE` }",
+                r#"append (from step_1 | group { `YEAR`, `PRODUCT` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `COUNTRY` = "All countries" } | select { `YEAR`, `COUNTRY`, `PRODUCT`, `VALUE` })"#,
+                r#"append (from step_1 | group { `YEAR`, `COUNTRY` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `PRODUCT` = "All products" } | select { `YEAR`, `COUNTRY`, `PRODUCT`, `VALUE` })"#,
+                r#"append (from step_1 | group { `YEAR` } ( aggregate { `VALUE` = sum `VALUE` } ) | derive { `COUNTRY` = "All countries", `PRODUCT` = "All products" } | select { `YEAR`, `COUNTRY`, `PRODUCT`, `VALUE` })"#,
+            ]
+            .join(" | ")
+        );
+    }
+
+    #[test]
+    fn totals_too_many_dimensions() {
+        let dimensions = (0..=MAX_TOTAL_DIMENSIONS)
+            .map(|index| json!({ "totalColumn": format!("COL{}", index), "totalRowsLabel": "All" }))
+            .collect::<Vec<_>>();
+        let input = json!({
+            "totalDimensions": dimensions,
+            "aggregations": [
+                { "columns": ["VALUE"], "newcolumns": ["VALUE"], "aggfunction": "sum" }
+            ]
+        });
+        let step = serde_json::from_value::<TotalsStep>(input).unwrap();
+        assert!(step.validate(None, &Dialect::Postgres).is_err());
+        assert!(step.to_prql_relation("step_1", &Dialect::Postgres).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::pipeline::steps::{distinct, with_derived_columns};
+use crate::translate::{Column, Dialect, Schema, ToPrql};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UniqueGroupsStep {
+    on: Vec<Column>,
+    #[serde(rename = "countColumn")]
+    count_column: Option<Column>,
+}
+
+impl ToPrql for UniqueGroupsStep {
+    fn to_prql(&self, dialect: &Dialect) -> Result<String> {
+        if self.on.is_empty() {
+            bail!("The uniquegroups step needs at least one column")
+        }
+        match &self.count_column {
+            // https://prql-lang.org/book/reference/stdlib/transforms/group.html
+            Some(count_column) => Ok(format!(
+                "group {{ {} }} ( aggregate {{ {} = count this }} )",
+                self.on
+                    .iter()
+                    .map(|col| col.to_prql(dialect))
+                    .collect::<Result<Vec<String>>>()?
+                    .join(", "),
+                count_column.to_prql(dialect)?
+            )),
+            None => distinct(&self.on, dialect),
+        }
+    }
+}
+
+impl Schema for UniqueGroupsStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        with_derived_columns(Some(self.on.clone()), &self.count_column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn uniquegroups_basic(#[case] dialect: Dialect) {
+        let input = json!({
+            "on": ["Country", "City"]
+        });
+        assert_eq!(
+            serde_json::from_value::<UniqueGroupsStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            "group { `Country`, `City` } ( take 1 ) | select { `Country`, `City` }"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn uniquegroups_with_count(#[case] dialect: Dialect) {
+        let input = json!({
+            "on": ["Country", "City"],
+            "countColumn": "rows count"
+        });
+        assert_eq!(
+            serde_json::from_value::<UniqueGroupsStep>(input)
+                .unwrap()
+                .to_prql(&dialect)
+                .unwrap(),
+            "group { `Country`, `City` } ( aggregate { `rows count` = count this } )"
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn uniquegroups_without_columns(#[case] dialect: Dialect) {
+        let input = json!({ "on": [] });
+        assert!(serde_json::from_value::<UniqueGroupsStep>(input)
+            .unwrap()
+            .to_prql(&dialect)
+            .is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::pipeline::steps::aggregate::group_aggregate;
+use crate::pipeline::steps::append;
+use crate::translate::{Column, Dialect, Schema, ToPrql, ToPrqlRelation, Value};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+const LABEL_COLUMN: &str = "LABEL_WATERFALL";
+const GROUP_COLUMN: &str = "GROUP_WATERFALL";
+const TYPE_COLUMN: &str = "TYPE_WATERFALL";
+// Keeps the start rows before the deltas, and the deltas before the end rows
+const RANK_COLUMN: &str = "__waterfall_rank";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaterfallStep {
+    #[serde(rename = "valueColumn")]
+    value_column: Column,
+    #[serde(rename = "milestonesColumn")]
+    milestones_column: Column,
+    start: Value,
+    end: Value,
+    #[serde(rename = "labelsColumn")]
+    labels_column: Column,
+    #[serde(default)]
+    groupby: Vec<Column>,
+    #[serde(rename = "parentsColumn")]
+    parents_column: Option<Column>,
+    #[serde(rename = "sortBy")]
+    sort_by: WaterfallSortBy,
+    order: WaterfallOrder,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum WaterfallSortBy {
+    Value,
+    Label,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum WaterfallOrder {
+    Asc,
+    Desc,
+}
+
+fn waterfall_column(name: &str, dialect: &Dialect) -> Result<String> {
+    Column(name.to_string()).to_prql(dialect)
+}
+
+impl WaterfallStep {
+    // Total of the values at a milestone, labelled with the milestone itself
+    fn milestone(
+        &self,
+        input: &str,
+        milestone: &Value,
+        rank: usize,
+        dialect: &Dialect,
+    ) -> Result<String> {
+        let value = self.value_column.to_prql(dialect)?;
+        let label = Value::String(match milestone {
+            Value::String(s) => s.clone(),
+            _ => milestone.to_string(),
+        });
+        Ok(format!(
+            "from {} | filter {} == {} | {} | derive {{ {} = {}, {} = {}, {} = null, {} = {} }}",
+            input,
+            self.milestones_column.to_prql(dialect)?,
+            milestone,
+            group_aggregate(
+                &self.groupby,
+                &[format!("{} = sum {}", value, value)],
+                dialect
+            )?,
+            waterfall_column(LABEL_COLUMN, dialect)?,
+            label,
+            waterfall_column(GROUP_COLUMN, dialect)?,
+            label,
+            waterfall_column(TYPE_COLUMN, dialect)?,
+            waterfall_column(RANK_COLUMN, dialect)?,
+            rank,
+        ))
+    }
+
+    // Difference between the end and start totals of each `label`, using a
+    // conditional aggregation to pivot both milestones into their own column
+    fn deltas(
+        &self,
+        input: &str,
+        label: &Column,
+        group: &Column,
+        waterfall_type: &str,
+        dialect: &Dialect,
+    ) -> Result<String> {
+        let milestones = self.milestones_column.to_prql(dialect)?;
+        let value = self.value_column.to_prql(dialect)?;
+        let mut on = self.groupby.clone();
+        if group.0 != label.0 {
+            on.push(group.clone());
+        }
+        on.push(label.clone());
+        Ok(format!(
+            "from {} | filter ({} == {} || {} == {}) | {} | derive {{ {} = {}, {} = {}, {} = {}, {} = `__end` - `__start`, {} = 1 }}",
+            input,
+            milestones,
+            self.start,
+            milestones,
+            self.end,
+            group_aggregate(
+                &on,
+                &[
+                    format!("`__start` = sum (case [ {} == {} => {}, true => 0 ])", milestones, self.start, value),
+                    format!("`__end` = sum (case [ {} == {} => {}, true => 0 ])", milestones, self.end, value),
+                ],
+                dialect
+            )?,
+            waterfall_column(LABEL_COLUMN, dialect)?,
+            label.to_prql(dialect)?,
+            waterfall_column(GROUP_COLUMN, dialect)?,
+            group.to_prql(dialect)?,
+            waterfall_column(TYPE_COLUMN, dialect)?,
+            Value::String(waterfall_type.to_string()),
+            value,
+            waterfall_column(RANK_COLUMN, dialect)?,
+        ))
+    }
+}
+
+impl Schema for WaterfallStep {
+    fn output_columns(&self, _input: Option<Vec<Column>>) -> Option<Vec<Column>> {
+        Some(
+            self.groupby
+                .iter()
+                .cloned()
+                .chain([LABEL_COLUMN, GROUP_COLUMN, TYPE_COLUMN].map(|col| Column(col.to_string())))
+                .chain([self.value_column.clone()])
+                .collect(),
+        )
+    }
+
+    // Milestones are compared to the values of a column, so they must be scalars
+    fn validate(&self, _input: Option<&[Column]>, _dialect: &Dialect) -> Result<()> {
+        for milestone in [&self.start, &self.end] {
+            if matches!(milestone, Value::Array(_) | Value::Object(_)) {
+                bail!(
+                    "Invalid waterfall milestone {}: expected a scalar value",
+                    milestone
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ToPrqlRelation for WaterfallStep {
+    // The start totals, the deltas of each label and the end totals are appended,
+    // then sorted
+    fn to_prql_relation(&self, input: &str, dialect: &Dialect) -> Result<String> {
+        let columns = self
+            .output_columns(None)
+            .unwrap_or_default()
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+        let rank = waterfall_column(RANK_COLUMN, dialect)?;
+
+        let mut parts = vec![self.milestone(input, &self.start, 0, dialect)?];
+        match &self.parents_column {
+            Some(parents) => {
+                parts.push(self.deltas(input, parents, parents, "parent", dialect)?);
+                parts.push(self.deltas(input, &self.labels_column, parents, "child", dialect)?);
+            }
+            None => parts.push(self.deltas(
+                input,
+                &self.labels_column,
+                &self.labels_column,
+                "parent",
+                dialect,
+            )?),
+        }
+        parts.push(self.milestone(input, &self.end, 2, dialect)?);
+        // `append` is positional, so every part must output its columns in the same order
+        let parts = parts
+            .iter()
+            .map(|part| format!("{} | select {{ {}, {} }}", part, columns, rank))
+            .collect::<Vec<String>>();
+
+        let sort_key = match self.sort_by {
+            WaterfallSortBy::Value => self.value_column.to_prql(dialect)?,
+            WaterfallSortBy::Label => waterfall_column(LABEL_COLUMN, dialect)?,
+        };
+        let sort_key = match self.order {
+            WaterfallOrder::Asc => sort_key,
+            WaterfallOrder::Desc => format!("-{}", sort_key),
+        };
+        let mut sort = self
+            .groupby
+            .iter()
+            .map(|col| col.to_prql(dialect))
+            .collect::<Result<Vec<String>>>()?;
+        sort.push(rank);
+        if self.parents_column.is_some() {
+            // Children are sorted right after their parent
+            sort.push(waterfall_column(GROUP_COLUMN, dialect)?);
+            sort.push(format!("-{}", waterfall_column(TYPE_COLUMN, dialect)?));
+        }
+        sort.push(sort_key);
+
+        Ok(format!(
+            "{} | sort {{ {} }} | select {{ {} }}",
+            append(&parts),
+            sort.join(", "),
+            columns
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn waterfall_basic(#[case] dialect: Dialect) {
+        let input = json!({
+            "valueColumn": "revenue",
+            "milestonesColumn": "year",
+            "start": 2019,
+            "end": 2020,
+            "labelsColumn": "city",
+            "sortBy": "value",
+            "order": "desc"
+        });
+        assert_eq!(
+            serde_json::from_value::<WaterfallStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            [
+                r#"from step_1 | filter `year` == 2019 | aggregate { `revenue` = sum `revenue` } | derive { `LABEL_WATERFALL` = "2019", `GROUP_WATERFALL` = "2019", `TYPE_WATERFALL` = null, `__waterfall_rank` = 0 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` }"#,
+                r#"append (from step_1 | filter (`year` == 2019 || `year` == 2020) | group { `city` } ( aggregate { `__start` = sum (case [ `year` == 2019 => `revenue`, true => 0 ]), `__end` = sum (case [ `year` == 2020 => `revenue`, true => 0 ]) } ) | derive { `LABEL_WATERFALL` = `city`, `GROUP_WATERFALL` = `city`, `TYPE_WATERFALL` = "parent", `revenue` = `__end` - `__start`, `__waterfall_rank` = 1 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` })"#,
+                r#"append (from step_1 | filter `year` == 2020 | aggregate { `revenue` = sum `revenue` } | derive { `LABEL_WATERFALL` = "2020", `GROUP_WATERFALL` = "2020", `TYPE_WATERFALL` = null, `__waterfall_rank` = 2 } | select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` })"#,
+                "sort { `__waterfall_rank`, -`revenue` }",
+                "select { `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue` }",
+            ]
+            .join(" | ")
+        );
+    }
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres)]
+    #[case::bigquery(Dialect::BigQuery)]
+    fn waterfall_with_parents_and_groups(#[case] dialect: Dialect) {
+        let input = json!({
+            "valueColumn": "revenue",
+            "milestonesColumn": "period",
+            "start": "Q1",
+            "end": "Q2",
+            "labelsColumn": "city",
+            "parentsColumn": "country",
+            "groupby": ["product"],
+            "sortBy": "label",
+            "order": "asc"
+        });
+        assert_eq!(
+            serde_json::from_value::<WaterfallStep>(input)
+                .unwrap()
+                .to_prql_relation("step_1", &dialect)
+                .unwrap(),
+            [
+                r#"from step_1 | filter `period` == "Q1" | group { `product` } ( aggregate { `revenue` = sum `revenue` } ) | derive { `LABEL_WATERFALL` = "Q1", `GROUP_WATERFALL` = "Q1", `TYPE_WATERFALL` = null, `__waterfall_rank` = 0 } | select { `product`, `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` }"#,
+                r#"append (from step_1 | filter (`period` == "Q1" || `period` == "Q2") | group { `product`, `country` } ( aggregate { `__start` = sum (case [ `period` == "Q1" => `revenue`, true => 0 ]), `__end` = sum (case [ `period` == "Q2" => `revenue`, true => 0 ]) } ) | derive { `LABEL_WATERFALL` = `country`, `GROUP_WATERFALL` = `country`, `TYPE_WATERFALL` = "parent", `revenue` = `__end` - `__start`, `__waterfall_rank` = 1 } | select { `product`, `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` })"#,
+                r#"append (from step_1 | filter (`period` == "Q1" || `period` == "Q2") | group { `product`, `country`, `city` } ( aggregate { `__start` = sum (case [ `period` == "Q1" => `revenue`, true => 0 ]), `__end` = sum (case [ `period` == "Q2" => `revenue`, true => 0 ]) } ) | derive { `LABEL_WATERFALL` = `city`, `GROUP_WATERFALL` = `country`, `TYPE_WATERFALL` = "child", `revenue` = `__end` - `__start`, `__waterfall_rank` = 1 } | select { `product`, `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` })"#,
+                r#"append (from step_1 | filter `period` == "Q2" | group { `product` } ( aggregate { `revenue` = sum `revenue` } ) | derive { `LABEL_WATERFALL` = "Q2", `GROUP_WATERFALL` = "Q2", `TYPE_WATERFALL` = null, `__waterfall_rank` = 2 } | select { `product`, `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue`, `__waterfall_rank` })"#,
+                "sort { `product`, `__waterfall_rank`, `GROUP_WATERFALL`, -`TYPE_WATERFALL`, `LABEL_WATERFALL` }",
+                "select { `product`, `LABEL_WATERFALL`, `GROUP_WATERFALL`, `TYPE_WATERFALL`, `revenue` }",
+            ]
+            .join(" | ")
+        );
+    }
+
+    #[rstest]
+    #[case::array(json!([2019]))]
+    #[case::object(json!({ "year": 2019 }))]
+    fn waterfall_non_scalar_milestone(#[case] start: serde_json::Value) {
+        let input = json!({
+            "valueColumn": "revenue",
+            "milestonesColumn": "year",
+            "start": start,
+            "end": 2020,
+            "labelsColumn": "city",
+            "sortBy": "value",
+            "order": "desc"
+        });
+        assert!(serde_json::from_value::<WaterfallStep>(input)
+            .unwrap()
+            .validate(None, &Dialect::Postgres)
+            .is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
+use prql_compiler::{compile, ErrorMessages, Options, Target};
 use serde::{Deserialize, Serialize};
 pub use serde_json::Value;
 
+use crate::pipeline::steps::{with_all_columns_replaced, with_replaced_columns};
 use crate::pipeline::*;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,17 +14,77 @@ pub enum Dialect {
     BigQuery,
 }
 
+impl Dialect {
+    /// Whether geographic types and functions are available (through PostGIS for Postgres)
+    pub fn supports_spatial(&self) -> bool {
+        match self {
+            Dialect::Postgres => true,
+            Dialect::BigQuery => true,
+        }
+    }
+}
+
+/// Compiles a PRQL query to SQL for the given dialect
+pub fn to_sql(prql: &str, dialect: &Dialect) -> Result<String, ErrorMessages> {
+    let target = match dialect {
+        Dialect::Postgres => Target::Sql(Some(prql_compiler::sql::Dialect::Postgres)),
+        Dialect::BigQuery => Target::Sql(Some(prql_compiler::sql::Dialect::BigQuery)),
+    };
+    let opts = Options {
+        format: false,
+        target,
+        signature_comment: false,
+        color: false,
+    };
+    compile(prql, &opts)
+}
+
 #[enum_dispatch]
 pub trait ToPrql {
     fn to_prql(&self, dialect: &Dialect) -> Result<String>;
 }
 
+/// Columns of the relation output by a step, when they can be known from those of its input
+#[enum_dispatch]
+pub trait Schema {
+    fn output_columns(&self, input: Option<Vec<Column>>) -> Option<Vec<Column>>;
+
+    /// Checks the step against the columns of its input, when they are known, and
+    /// against what the dialect supports
+    fn validate(&self, _input: Option<&[Column]>, _dialect: &Dialect) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Implemented by steps which replace columns of their input in place. The other
+/// columns have to be known to be kept, as `*` cannot exclude any in SQL, except
+/// with BigQuery and its `SELECT * REPLACE`
+pub trait ToPrqlReplacing {
+    /// Replaced columns, with the SQL expressions of s-strings computing them
+    fn replacements(&self, dialect: &Dialect) -> Result<Vec<(Column, String)>>;
+
+    fn to_prql_replacing(&self, input: Option<&[Column]>, dialect: &Dialect) -> Result<String> {
+        with_replaced_columns(input, &self.replacements(dialect)?, dialect)
+    }
+
+    /// Renders the step from the previous steps, given as PRQL, whose columns are unknown
+    fn to_prql_replacing_all(&self, previous: &str, dialect: &Dialect) -> Result<String> {
+        with_all_columns_replaced(previous, &self.replacements(dialect)?, dialect)
+    }
+}
+
+/// Implemented by steps which need to refer to the relation produced by the
+/// previous steps by name, e.g. to `append` several transformations of it.
+pub trait ToPrqlRelation {
+    fn to_prql_relation(&self, input: &str, dialect: &Dialect) -> Result<String>;
+}
+
 #[enum_dispatch]
 pub trait ToSString {
     fn to_s_string(&self, dialect: &Dialect) -> Result<String>;
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Column(pub String);
 
 impl ToPrql for Column {
@@ -44,13 +106,19 @@ impl ToSString for String {
     fn to_s_string(&self, dialect: &Dialect) -> Result<String> {
         // We need to use single quotes for values in s-strings
         // (see https://prql-lang.org/book/reference/syntax/s-strings.html#admonition-note)
-        let single_quote_replacement = match dialect {
-            Dialect::Postgres => "''",
-            Dialect::BigQuery => r#"\\'"#,
+        // Backslashes are escape characters in BigQuery literals but not in Postgres ones
+        let (backslash_replacement, single_quote_replacement) = match dialect {
+            Dialect::Postgres => (r"\\", "''"),
+            Dialect::BigQuery => (r"\\\\", r#"\\'"#),
         };
+        // Double quotes, backslashes and braces also have a meaning in s-strings
         Ok(format!(
             "\'{}\'",
-            self.replace('\'', single_quote_replacement)
+            self.replace('\\', backslash_replacement)
+                .replace('\'', single_quote_replacement)
+                .replace('"', r#"\""#)
+                .replace('{', "{{")
+                .replace('}', "}}")
         ))
     }
 }
@@ -63,3 +131,22 @@ impl ToSString for Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::postgres(Dialect::Postgres, r#"SELECT * FROM t WHERE a = 'it''s {b} "c" \d'"#)]
+    #[case::bigquery(Dialect::BigQuery, r#"SELECT * FROM t WHERE a = 'it\'s {b} "c" \\d'"#)]
+    fn string_to_s_string(#[case] dialect: Dialect, #[case] sql: &str) {
+        let literal = r#"it's {b} "c" \d"#.to_string().to_s_string(&dialect).unwrap();
+        assert_eq!(
+            to_sql(&format!(r#"from t | filter s"a = {}""#, literal), &dialect).unwrap(),
+            sql
+        );
+    }
+}